@@ -52,6 +52,12 @@ impl ArgDef {
             _ => false,
         }
     }
+    /// `GuileCtx` arguments only prove guile mode, they are never passed to the C function
+    pub fn is_ctx(&self) -> bool {
+        if let &ArgDef::Type(syn::Type::Path(ref tp)) = self {
+            tp.path.segments.iter().last().map_or(false, |s| s.ident.as_ref() == "GuileCtx")
+        } else { false }
+    }
 }
 
 impl Synom for ArgDef {
@@ -174,8 +180,24 @@ impl GuileDef {
         let mut tokens = if self.public {quote!(pub)} else {quote::Tokens::new()};
 
         let name   = self.name;
+        let re = Regex::new("a[0-9]+").unwrap();
+        let mut used_cargs = HashSet::new();
+        self.cargs.iter().for_each(|e| {
+            if let CArgDef::Immediate(ref expr) = *e {
+                let mut ts = Tokens::new();
+                expr.to_tokens(&mut ts);
+                for m in re.find_iter(&ts.to_string()) {
+                    used_cargs.insert(m.as_str().to_string());
+                }
+            }
+        });
         let args   = self.args.iter().enumerate().map(|(i, e)| {
             match *e {
+                // unused contexts get an underscore to keep rustc quiet
+                ArgDef::Type(ref t) if e.is_ctx() && !used_cargs.contains(&format!("a{}", i)) => {
+                    let n = syn::Ident::from(format!("_a{}", i));
+                    quote!(#n: #t)
+                },
                 ArgDef::Type(ref t)        |
                 ArgDef::TypeBound(ref t,_) => {
                     let n = syn::Ident::from(format!("a{}", i));
@@ -194,17 +216,6 @@ impl GuileDef {
 
         let ret_ty = self.ret_ty.as_ref().map_or(quote!(()), |ref rt| rt.into_tokens());
         let cfunc  = self.cfunc;
-        let re = Regex::new("a[0-9]+").unwrap();
-        let mut used_cargs = HashSet::new();
-        self.cargs.iter().for_each(|e| {
-            if let CArgDef::Immediate(ref expr) = *e {
-                let mut ts = Tokens::new();
-                expr.to_tokens(&mut ts);
-                for m in re.find_iter(&ts.to_string()) {
-                    used_cargs.insert(m.as_str().to_string());
-                }
-            }
-        });
         let cargs: Vec<syn::Expr>  = self.cargs.iter().flat_map(|e| -> Vec<syn::Expr> {
             let ee = e.clone();
             match ee {
@@ -214,7 +225,7 @@ impl GuileDef {
                         let all: Vec<String> = self.args
                             .iter()
                             .enumerate()
-                            .filter(|&(_, e)| !e.is_self())
+                            .filter(|&(_, e)| !e.is_self() && !e.is_ctx())
                             .map(|(i,_)| format!("a{}", i)).collect();
                         for a in all.iter().filter(|&e| !used_cargs.contains(e)) {
                             if raw {
@@ -242,6 +253,11 @@ impl GuileDef {
             }
         }
 
+        let args: Vec<_>   = args.collect();
+        let bounds: Vec<_> = bounds.collect();
+        let (args, bounds) = (&args, &bounds);
+        let public = if self.public { quote!(pub) } else { quote!() };
+
         let _self = if self.self_disabled { quote!() } else { quote!(&self,) };
         tokens = quote!(
                 #tokens fn #name<#(#bounds),*>(#_self #(#args),*) -> #ret_ty {
@@ -249,6 +265,17 @@ impl GuileDef {
                 }
         );
 
+        // exception-safe twin, running the call under a catch-all
+        let try_name = syn::Ident::from(format!("try_{}", name));
+        let try_doc  = format!("Like `{}`, but returns scheme exceptions as a `GuileError`", name);
+        tokens = quote!(
+                #tokens
+                #[doc = #try_doc]
+                #public fn #try_name<#(#bounds),*>(#_self #(#args),*) -> Result<#ret_ty, ::error::GuileError> {
+                    ::interp::Guile::try_call(move || { #body })
+                }
+        );
+
         tokens
     }
 }
@@ -261,14 +288,16 @@ named!(parse_guile_defs -> Vec<GuileDef>, do_parse!(
 ));
 
 named!(parse_guile_impl -> TokenStream, do_parse!(
-        // TODO: imitate syn::ItemImpl with generics and things
+        // TODO: imitate syn::ItemImpl with where clauses and things
+        option!(keyword!(impl)) >>
+        generics: syn!(syn::Generics) >>
         impld: syn!(syn::Type) >>
         body: map!(braces!(many0!(alt!(
                     tuple!(syn!(GuileDef), option!(punct!(;))) => { |(gd, _)| gd.construct().into_tokens().into() }
                     |
                     syn!(syn::ImplItem) => { |i| i.into_tokens().into() }
                     ))), |(_, c): (_, Vec<TokenStream>)| c) >>
-        (quote!( impl #impld {
+        (quote!( impl #generics #impld {
                     #(#body)*
                 }).into())
 
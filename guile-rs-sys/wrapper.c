@@ -34,6 +34,8 @@ gen_macro_proxy_2(SCM, scm_cons, SCM, SCM);
 gen_macro_proxy_0(SCM, SCM_BOOL_F);
 gen_macro_proxy_0(SCM, SCM_BOOL_T);
 gen_macro_proxy_0(SCM, SCM_UNDEFINED);
+gen_macro_proxy_0(SCM, SCM_EOL);
+gen_macro_proxy_0(SCM, SCM_UNSPECIFIED);
 
 SCM gu_scm_list_n(SCM* elts) {
     SCM answer = SCM_EOL;
@@ -48,6 +50,33 @@ SCM gu_scm_list_n(SCM* elts) {
 
 int gu_scm_is_string(SCM);
 
+// flags as plain ints, bindgen makes rust enums of the flag enums
+gen_macro_proxy_0(int, SCM_F_DYNWIND_REWINDABLE);
+gen_macro_proxy_0(int, SCM_F_WIND_EXPLICITLY);
+
+void gu_scm_dynwind_begin(int flags) {
+    scm_dynwind_begin(flags);
+}
+
+void gu_scm_dynwind_unwind_handler(void (*func)(void *), void *data, int flags) {
+    scm_dynwind_unwind_handler(func, data, flags);
+}
+
+void gu_scm_dynwind_rewind_handler(void (*func)(void *), void *data, int flags) {
+    scm_dynwind_rewind_handler(func, data, flags);
+}
+
+void gu_scm_dynwind_unwind_handler_with_scm(void (*func)(SCM), SCM data, int flags) {
+    scm_dynwind_unwind_handler_with_scm(func, data, flags);
+}
+
+void gu_scm_dynwind_rewind_handler_with_scm(void (*func)(SCM), SCM data, int flags) {
+    scm_dynwind_rewind_handler_with_scm(func, data, flags);
+}
+
+gen_macro_proxy(scm_t_bits, SCM_STREAM, SCM);
+gen_macro_proxy(signed char*, SCM_BYTEVECTOR_CONTENTS, SCM);
+
 int gu_scm_is_string(SCM x) {
     return scm_is_string(x);
 }
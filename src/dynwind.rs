@@ -0,0 +1,225 @@
+//! Rust cleanup for scheme non-local exits
+//!
+//! A scheme `throw` (or an escaping continuation) leaves rust frames without running their
+//! destructors. Cleanup registered on a dynwind context runs whichever way the context is
+//! left.
+//!
+//! # Example
+//! ```rust,ignore
+//! Guile::dynwind(ctx, |frame| {
+//!     let mut buf = frame.guard(Vec::new());
+//!     frame.on_unwind(false, || println!("left by a throw"));
+//!
+//!     buf.push(1);
+//!     scm_eval!{ ctx, (throw 'oops) };
+//!     // not reached, `buf` is still dropped by the unwind
+//! });
+//! ```
+
+use libc;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use interp::{Guile, GuileCtx};
+use unwind::{self, Boundary};
+
+use guile_rs_sys::*;
+
+
+/// A dynwind context, open for the duration of `Guile::dynwind`
+///
+/// Handlers and owned values are released in reverse order of registration. They may
+/// only borrow from outside the `Guile::dynwind` call (`'w`), as they can run after a
+/// throw has already left the frames of its closure.
+pub struct DynwindFrame<'w> {
+    // invariant, so `'w` can't be shortened to a borrow of the closure's locals
+    scope: PhantomData<*mut &'w ()>,
+}
+
+impl<'w> DynwindFrame<'w> {
+    /// Call `fun` when the context is left
+    ///
+    /// With `explicit` it also runs on a normal exit, otherwise only on a non-local exit.
+    pub fn on_unwind<F: FnMut() + 'w>(&self, explicit: bool, fun: F) {
+        let data = self.register(Box::new(fun)) as *mut libc::c_void;
+        unsafe { gu_scm_dynwind_unwind_handler(Some(call_handler::<F>), data, wind_flags(explicit)); }
+    }
+
+    /// Take ownership of a heap allocation, dropping it when the context is left
+    ///
+    /// Like `scm_dynwind_free`, the value is freed however the context is left. The
+    /// returned handle gives access to it until then.
+    pub fn free<'f, T: 'w>(&'f self, value: Box<T>) -> Owned<'f, T> {
+        Owned { data: self.register(value), frame: PhantomData }
+    }
+
+    /// Keep `value` until the context is left, see `free`
+    #[inline]
+    pub fn own<'f, T: 'w>(&'f self, value: T) -> Owned<'f, T> {
+        self.free(Box::new(value))
+    }
+
+    /// Wrap `value` in a guard dropping it on a normal exit from rust or when the context
+    /// is left by scheme, whichever comes first
+    pub fn guard<'f, T: 'w>(&'f self, value: T) -> Guard<'f, T> {
+        Guard { value: self.own(Some(value)) }
+    }
+
+    // drop `value` when the context is left
+    fn register<T: 'w>(&self, value: Box<T>) -> *mut T {
+        let data = Box::into_raw(value);
+        unsafe { gu_scm_dynwind_unwind_handler(Some(drop_handler::<T>), data as *mut libc::c_void, wind_flags(true)); }
+        data
+    }
+}
+
+/// A value owned by a dynwind context, see `DynwindFrame::own`
+///
+/// Each handle is the only access to its value, which stays allocated while the frame is
+/// borrowed.
+pub struct Owned<'f, T: 'f> {
+    data:  *mut T,
+    frame: PhantomData<&'f mut T>,
+}
+
+impl<'f, T> Deref for Owned<'f, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T { unsafe { &*self.data } }
+}
+
+impl<'f, T> DerefMut for Owned<'f, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.data } }
+}
+
+/// A dynwind context that can be re-entered, open for the duration of
+/// `Guile::dynwind_rewindable`
+///
+/// Handlers can run again after the context was left, so they are owned by the GC and
+/// dropped once nothing can re-enter the context anymore. Values can't be owned or guarded
+/// by it: dropped on the first exit, they would be used after free on a re-entry.
+pub struct RewindableFrame {
+    // the handlers are only registered on the thread of the context
+    _thread: PhantomData<*mut ()>,
+}
+
+impl RewindableFrame {
+    /// Call `fun` each time the context is left, see `DynwindFrame::on_unwind`
+    pub fn on_unwind<F: FnMut() + Send + 'static>(&self, explicit: bool, fun: F) {
+        unsafe { gu_scm_dynwind_unwind_handler_with_scm(Some(call_scm_handler::<F>), Self::gc_owned(fun), wind_flags(explicit)); }
+    }
+
+    /// Call `fun` each time the context is re-entered, with `explicit` also right away
+    pub fn on_rewind<F: FnMut() + Send + 'static>(&self, explicit: bool, fun: F) {
+        unsafe { gu_scm_dynwind_rewind_handler_with_scm(Some(call_scm_handler::<F>), Self::gc_owned(fun), wind_flags(explicit)); }
+    }
+
+    // the context keeps the pointer alive for as long as it can be re-entered
+    unsafe fn gc_owned<F>(fun: F) -> SCM {
+        scm_from_pointer(Box::into_raw(Box::new(fun)) as *mut libc::c_void, Some(drop_handler::<F>))
+    }
+}
+
+/// A value dropped on both normal and abnormal exit, see `DynwindFrame::guard`
+pub struct Guard<'f, T: 'f> {
+    // emptied by the first of our drop and the context's unwind
+    value: Owned<'f, Option<T>>,
+}
+
+impl<'f, T> Guard<'f, T> {
+    /// Take the value out, it is then no longer dropped by the context
+    #[inline]
+    pub fn into_inner(mut self) -> T {
+        let value = self.value.take().expect("guard already released");
+        // nothing left to drop
+        mem::forget(self);
+        value
+    }
+}
+
+impl<'f, T> Deref for Guard<'f, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T { self.value.as_ref().unwrap() }
+}
+
+impl<'f, T> DerefMut for Guard<'f, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T { self.value.as_mut().unwrap() }
+}
+
+impl<'f, T> Drop for Guard<'f, T> {
+    fn drop(&mut self) {
+        self.value.take();
+    }
+}
+
+#[inline]
+fn wind_flags(explicit: bool) -> libc::c_int {
+    if explicit { unsafe { gu_SCM_F_WIND_EXPLICITLY() } } else { 0 }
+}
+
+// handlers are called from libguile, panics are kept for the next return to rust
+unsafe extern "C" fn call_handler<F: FnMut()>(data: *mut libc::c_void) {
+    let fun = &mut *(data as *mut F);
+    unwind::catch_deferred(fun);
+}
+
+unsafe extern "C" fn call_scm_handler<F: FnMut()>(data: SCM) {
+    let fun = &mut *(scm_to_pointer(data) as *mut F);
+    unwind::catch_deferred(fun);
+}
+
+// also the finalizer of the handlers of rewindable contexts, run by the GC
+unsafe extern "C" fn drop_handler<T>(data: *mut libc::c_void) {
+    let value = Box::from_raw(data as *mut T);
+    unwind::catch_deferred(move || drop(value));
+}
+
+// closes the context when rust leaves it, by a return or a panic
+struct DynwindEnd;
+
+impl Drop for DynwindEnd {
+    fn drop(&mut self) {
+        unsafe { scm_dynwind_end(); }
+    }
+}
+
+impl Guile {
+    /// Run `fun` inside a new dynwind context
+    ///
+    /// The cleanup registered on the frame runs when `fun` returns or panics, and when a
+    /// scheme throw leaves it. The context can not be re-entered by a continuation, trying
+    /// to re-enter it throws a scheme error instead.
+    pub fn dynwind<'g, 'w, F, R>(_ctx: GuileCtx<'g>, fun: F) -> R
+        where F: for<'f> FnOnce(&'f DynwindFrame<'w>) -> R {
+        unsafe { Self::_dynwind(0, || fun(&DynwindFrame { scope: PhantomData })) }
+    }
+
+    /// Like `dynwind`, but the context can be re-entered through a continuation
+    ///
+    /// Re-entering resumes the rust frames of `fun` after they may have returned, `fun`
+    /// has to be written so this never happens (e.g. by only capturing continuations that
+    /// don't escape it), which the compiler can not check.
+    pub unsafe fn dynwind_rewindable<'g, F, R>(_ctx: GuileCtx<'g>, fun: F) -> R
+        where F: for<'f> FnOnce(&'f RewindableFrame) -> R {
+        Self::_dynwind(gu_SCM_F_DYNWIND_REWINDABLE(), || fun(&RewindableFrame { _thread: PhantomData }))
+    }
+
+    unsafe fn _dynwind<F: FnOnce() -> R, R>(flags: libc::c_int, fun: F) -> R {
+        // panics of the handlers run by the end come back here
+        let boundary = Boundary::open();
+        gu_scm_dynwind_begin(flags);
+        let end = DynwindEnd;
+
+        let ret = fun();
+
+        drop(end);
+        boundary.close();
+        ret
+    }
+}
@@ -0,0 +1,91 @@
+//! Isolated top levels for evaluation
+//!
+//! Every plugin or scratch buffer can get its own `Environment`, so its definitions don't
+//! leak into the others or into `(guile-user)`.
+//!
+//! # Example
+//! ```rust,ignore
+//! let a = Environment::fresh_user_module(ctx)?;
+//! let b = Environment::fresh_user_module(ctx)?;
+//!
+//! a.eval("(define x 1)")?;
+//! assert!(b.eval("x").unwrap_err().is_unbound_variable());
+//! ```
+
+use std::ffi::CString;
+
+use scm::{Scm, Untyped, Module, Variable, TypeSpec, FromScm, Rooted};
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+/// A module used as the top level of evaluations
+///
+/// All evaluations catch throws, returning them as `GuileError`s with their backtrace.
+///
+/// The module is protected from the GC for as long as the environment lives, so it can be
+/// kept in a `Vec` or a `Box`.
+#[derive(Debug)]
+pub struct Environment<'g> {
+    module: Scm<'g, Module>,
+    // the environment may hold the only reference to an anonymous module
+    _root:  Rooted<Module>,
+}
+
+impl<'g> Environment<'g> {
+    /// A new anonymous module with the bindings of `(guile-user)`, see `make-fresh-user-module`
+    pub fn fresh_user_module(_ctx: GuileCtx<'g>) -> Result<Environment<'g>, GuileError> {
+        let (guile, make) = (CString::new("guile").unwrap(), CString::new("make-fresh-user-module").unwrap());
+
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || unsafe {
+            scm_call_0(scm_c_public_ref(guile.as_ptr(), make.as_ptr()))
+        }).map(|module| Environment::from_module(Scm::_from_raw(module)))
+    }
+
+    /// The environment of the current module
+    #[inline]
+    pub fn current(ctx: GuileCtx<'g>) -> Environment<'g> {
+        Environment::from_module(Scm::<Module>::current(ctx))
+    }
+
+    /// Evaluate in an existing module
+    #[inline]
+    pub fn from_module(module: Scm<'g, Module>) -> Environment<'g> {
+        Environment { _root: Rooted::new(Scm::_from_raw(module.data)), module }
+    }
+
+    #[inline]
+    pub fn module(&self) -> &Scm<'g, Module> { &self.module }
+
+    /// Evaluate the expressions in `s`, returning the value of the last one
+    #[inline]
+    pub fn eval(&self, s: &str) -> Result<Scm<'g, Untyped>, GuileError> {
+        Guile::try_eval_in(&self.module, s)
+    }
+
+    /// Evaluate an expression that has already been read
+    #[inline]
+    pub fn eval_scm<TS: TypeSpec>(&self, expr: &Scm<'g, TS>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Guile::try_eval_scm(expr, &self.module)
+    }
+
+    /// Evaluate `s` and convert the result, see `Guile::eval_as`
+    pub fn eval_as<T: FromScm<'g>>(&self, s: &str) -> Result<T, GuileError> {
+        let value = self.eval(s)?;
+        T::from_scm(Scm::_from_raw(value.data)).ok_or_else(|| GuileError::wrong_type("eval_as", &value))
+    }
+
+    /// Define `name` as `value` at this top level
+    #[inline]
+    pub fn define<TS: TypeSpec>(&self, name: &str, value: &Scm<TS>) -> Scm<'g, Variable> {
+        self.module.define_value(name, value)
+    }
+
+    /// The value of `name` at this top level, `None` if not defined
+    #[inline]
+    pub fn lookup(&self, name: &str) -> Option<Scm<'g, Untyped>> {
+        self.module.variable(name).and_then(|var| var.get())
+    }
+}
@@ -0,0 +1,385 @@
+//! Scheme exceptions as rust values
+
+use std::backtrace::{Backtrace as RustBacktrace, BacktraceStatus};
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use scm::{Scm, TypeSpec};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx, RootProc};
+use interrupt::interrupt_key;
+
+use guile_rs_sys::*;
+
+/// A scheme exception caught at the rust boundary
+///
+/// Holds the same data `scm_display_error` prints: the throw key, the procedure that
+/// threw and the message with its irritants filled in.
+///
+/// # Example
+/// ```rust,ignore
+/// let err = Guile::eval_as::<i32>(ctx, "(car 5)").unwrap_err();
+/// assert!(err.is_wrong_type_arg());
+/// assert_eq!(err.subr, Some("car".to_string()));
+/// assert_eq!(err.to_string(), "In procedure car: Wrong type argument in position 1 (expecting pair): 5");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuileError {
+    /// The throw key, like `wrong-type-arg`
+    pub key: String,
+    /// Name of the procedure that threw, if known
+    pub subr: Option<String>,
+    /// The message, with its `~A` and `~S` directives already formatted
+    pub message: String,
+    /// The message arguments, as written by `write`
+    pub irritants: Vec<String>,
+    /// Where the throw happened, only recorded by the catching functions of `Guile`
+    pub backtrace: Option<Backtrace>,
+    /// The source file the error comes from, for read errors and errors of loaded files
+    pub location: Option<Location>,
+    // thrown by an `InterruptHandle`, not just to a key named `interrupted`
+    interrupted: bool,
+}
+
+impl GuileError {
+    // convert the key and args of a throw, only call in guile mode
+    pub(crate) unsafe fn from_raw(key: SCM, args: SCM) -> GuileError {
+        let interrupted = gu_scm_is_eq(key, interrupt_key());
+        let key = if gu_scm_is_symbol(key) == 1 { Self::string(scm_symbol_to_string(key)) }
+                  else { Self::write(key) };
+
+        // errors raised with `scm-error` have the shape (subr message irritants rest)
+        if scm_ilength(args) == 4 {
+            let subr = scm_list_ref(args, scm_from_int32(0));
+            let msg  = scm_list_ref(args, scm_from_int32(1));
+            let irr  = scm_list_ref(args, scm_from_int32(2));
+            let irr  = if gu_scm_is_false(irr) { gu_SCM_EOL() } else { irr };
+
+            let subr_ok = gu_scm_is_false(subr) || gu_scm_is_symbol(subr) == 1 || gu_scm_is_string(subr) == 1;
+            if subr_ok && gu_scm_is_string(msg) == 1 && scm_ilength(irr) >= 0 {
+                let subr = if gu_scm_is_false(subr)          { None }
+                           else if gu_scm_is_symbol(subr) == 1 { Some(Self::string(scm_symbol_to_string(subr))) }
+                           else                                { Some(Self::string(subr)) };
+
+                // a message not matching its irritants throws again, keep it unformatted then
+                let message = Guile::catch_raw(gu_SCM_BOOL_T(), || scm_simple_format(gu_SCM_BOOL_F(), msg, irr))
+                    .map(|m| Self::string(m))
+                    .unwrap_or_else(|_| Self::string(msg));
+
+                // read errors have the irritants (file line column message)
+                let location = if key == "read-error" && scm_ilength(irr) == 4 {
+                    Location::from_raw(scm_list_ref(irr, scm_from_int32(0)),
+                                       scm_list_ref(irr, scm_from_int32(1)),
+                                       scm_list_ref(irr, scm_from_int32(2)))
+                } else {
+                    None
+                };
+
+                return GuileError { key, subr, message, irritants: Self::write_list(irr), backtrace: None, location, interrupted };
+            }
+        }
+
+        // any other throw, the args are all there is
+        let message = format!("Throw to key `{}' with args `{}'.", key, Self::write(args));
+        let irritants = if scm_ilength(args) >= 0 { Self::write_list(args) } else { vec![Self::write(args)] };
+
+        GuileError { key, subr: None, message, irritants, backtrace: None, location: None, interrupted }
+    }
+
+    // attach the scheme frames of the throw, along with the rust stack catching it
+    pub(crate) fn with_backtrace(mut self, frames: Vec<Frame>) -> GuileError {
+        self.backtrace = Some(Backtrace {
+            frames,
+            rust: Arc::new(RustBacktrace::capture()),
+        });
+        self
+    }
+
+    // error with no procedure and a plain message
+    pub(crate) fn new(key: &str, message: &str) -> GuileError {
+        GuileError {
+            key:         key.to_string(),
+            subr:        None,
+            message:     message.to_string(),
+            irritants:   vec![],
+            backtrace:   None,
+            location:    None,
+            interrupted: false,
+        }
+    }
+
+    // arguments to `scm_error_scm` rethrowing this error, only call in guile mode
+    // the message is passed preformatted, as the only irritant
+    pub(crate) unsafe fn into_raw(self) -> (SCM, SCM, SCM, SCM) {
+        let key  = if self.interrupted { interrupt_key() }
+                   else { scm_from_utf8_symbol(CString::new(self.key).unwrap().as_ptr()) };
+        let subr = match self.subr {
+            Some(subr) => Scm::<ScmString>::from_str(GuileCtx::new(), &subr).data,
+            None       => gu_SCM_BOOL_F(),
+        };
+        let msg  = Scm::<ScmString>::from_str(GuileCtx::new(), "~A").data;
+        let args = gu_scm_cons(Scm::<ScmString>::from_str(GuileCtx::new(), &self.message).data, gu_SCM_EOL());
+
+        (key, subr, msg, args)
+    }
+
+    // error for a value that does not convert to the wanted rust type
+    pub(crate) fn wrong_type<'g, TS: TypeSpec>(subr: &str, value: &Scm<'g, TS>) -> GuileError {
+        let written = unsafe { Self::write(value.data) };
+        GuileError {
+            key:         "wrong-type-arg".to_string(),
+            subr:        Some(subr.to_string()),
+            message:     format!("Wrong type to convert: {}", written),
+            irritants:   vec![written],
+            backtrace:   None,
+            location:    None,
+            interrupted: false,
+        }
+    }
+
+    // locate an error of loading `file`, at the innermost frame of a loaded file
+    //
+    // An error raised in a file that `file` loads is located in that file. Frames of guile's
+    // own modules have paths relative to the load path, they are skipped by only taking the
+    // files found from here.
+    pub(crate) fn in_file(mut self, file: &str) -> GuileError {
+        if self.location.is_none() {
+            let frame = self.backtrace.as_ref().and_then(|bt| bt.frames.iter().find(|f| {
+                f.file.as_ref().map_or(false, |f| file.ends_with(f.as_str()) || Path::new(f).is_file())
+            }));
+
+            self.location = Some(match frame {
+                Some(&Frame { file: Some(ref inner), line, column, .. }) if !file.ends_with(inner.as_str()) =>
+                    Location { file: inner.clone(), line, column },
+                Some(frame) => Location { file: file.to_string(), line: frame.line, column: frame.column },
+                None        => Location { file: file.to_string(), line: None, column: None },
+            });
+        }
+        self
+    }
+
+    /// `wrong-type-arg`, a procedure got an argument of the wrong type
+    #[inline]
+    pub fn is_wrong_type_arg(&self) -> bool { self.key == "wrong-type-arg" }
+
+    /// `unbound-variable`, a reference to an undefined variable
+    #[inline]
+    pub fn is_unbound_variable(&self) -> bool { self.key == "unbound-variable" }
+
+    /// `interrupted`, an evaluation stopped by an `InterruptHandle` or a timeout
+    ///
+    /// Scheme code throwing to a key named `interrupted` is not taken for one.
+    #[inline]
+    pub fn is_interrupted(&self) -> bool { self.interrupted }
+
+    /// `out-of-range`, an index or integer conversion out of bounds
+    #[inline]
+    pub fn is_out_of_range(&self) -> bool { self.key == "out-of-range" }
+
+    unsafe fn string(s: SCM) -> String {
+        Scm::<ScmString>::_from_raw(s).to_string()
+    }
+
+    // the object as written by `write`, only call in guile mode
+    pub(crate) unsafe fn write(obj: SCM) -> String {
+        Self::string(scm_object_to_string(obj, gu_SCM_UNDEFINED()))
+    }
+
+    unsafe fn write_list(mut list: SCM) -> Vec<String> {
+        let mut v = Vec::new();
+        while gu_scm_is_pair(list) == 1 {
+            v.push(Self::write(gu_scm_car(list)));
+            list = gu_scm_cdr(list);
+        }
+        v
+    }
+}
+
+impl fmt::Display for GuileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref location) = self.location {
+            write!(f, "{}: ", location)?;
+        }
+        match self.subr {
+            Some(ref subr) => write!(f, "In procedure {}: {}", subr, self.message),
+            None           => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for GuileError {}
+
+/// Errors of rust code called from scheme, thrown with the `rust-error` key
+impl From<String> for GuileError {
+    fn from(message: String) -> GuileError {
+        GuileError::new("rust-error", &message)
+    }
+}
+
+impl<'a> From<&'a str> for GuileError {
+    fn from(message: &'a str) -> GuileError {
+        GuileError::new("rust-error", message)
+    }
+}
+
+
+/// A position in a source file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file:   String,
+    /// Line in `file`, starting at 1
+    pub line:   Option<u32>,
+    /// Column in `line`, starting at 0
+    pub column: Option<u32>,
+}
+
+impl Location {
+    unsafe fn from_raw(file: SCM, line: SCM, column: SCM) -> Option<Location> {
+        if gu_scm_is_string(file) != 1 {
+            return None;
+        }
+        Some(Location { file: GuileError::string(file), line: Frame::opt_u32(line), column: Frame::opt_u32(column) })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(col)) => write!(f, "{}:{}:{}", self.file, line, col),
+            (Some(line), None)      => write!(f, "{}:{}", self.file, line),
+            _                       => write!(f, "{}", self.file),
+        }
+    }
+}
+
+
+/// A frame of the scheme stack
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Name of the called procedure, if it has one
+    pub procedure: Option<String>,
+    pub file:      Option<String>,
+    /// Line in `file`, starting at 1
+    pub line:      Option<u32>,
+    /// Column in `line`, starting at 0
+    pub column:    Option<u32>,
+    /// The arguments of the call, as written by `write`
+    pub args:      Vec<String>,
+}
+
+// takes the stack of the current throw, as a list of
+// (name file line column (args ...)) with all but numbers as strings
+const CAPTURE_STACK: &str = "
+(lambda ()
+  (let ((stack (make-stack #t 1)))
+    (map (lambda (i)
+           (let* ((frame (stack-ref stack i))
+                  (name  (frame-procedure-name frame))
+                  (src   (frame-source frame)))
+             (list (and name (format #f \"~a\" name))
+                   (and src ((@ (system vm program) source:file) src))
+                   (and src ((@ (system vm program) source:line-for-user) src))
+                   (and src ((@ (system vm program) source:column) src))
+                   (map (lambda (a) (format #f \"~s\" a))
+                        ((@ (system vm frame) frame-arguments) frame)))))
+         (iota (if stack (stack-length stack) 0)))))";
+
+static CAPTURE: RootProc = RootProc::new(CAPTURE_STACK);
+
+impl Frame {
+    // frames of the current stack, innermost first
+    // only call in guile mode, from a pre-unwind handler to get the stack of a throw
+    pub(crate) unsafe fn capture() -> Vec<Frame> {
+        // a failing capture must not replace the original error
+        let frames = Guile::catch_raw(gu_SCM_BOOL_T(), || scm_call_0(CAPTURE.get()));
+
+        let mut v = Vec::new();
+        if let Ok(mut list) = frames {
+            while gu_scm_is_pair(list) == 1 {
+                let f = gu_scm_car(list);
+                let field = |i| scm_list_ref(f, scm_from_int32(i));
+
+                v.push(Frame {
+                    procedure: Self::opt_string(field(0)),
+                    file:      Self::opt_string(field(1)),
+                    line:      Self::opt_u32(field(2)),
+                    column:    Self::opt_u32(field(3)),
+                    args:      {
+                        let mut args = Vec::new();
+                        let mut a = field(4);
+                        while gu_scm_is_pair(a) == 1 {
+                            args.push(GuileError::string(gu_scm_car(a)));
+                            a = gu_scm_cdr(a);
+                        }
+                        args
+                    },
+                });
+                list = gu_scm_cdr(list);
+            }
+        }
+        v
+    }
+
+    unsafe fn opt_string(s: SCM) -> Option<String> {
+        if gu_scm_is_string(s) == 1 { Some(GuileError::string(s)) } else { None }
+    }
+
+    unsafe fn opt_u32(n: SCM) -> Option<u32> {
+        if scm_is_exact_integer(n) == 1 { Some(scm_to_uint32(n)) } else { None }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}", self.procedure.as_ref().map(|p| p.as_str()).unwrap_or("_"))?;
+        for a in &self.args {
+            write!(f, " {}", a)?;
+        }
+        write!(f, ")")?;
+
+        match (&self.file, self.line, self.column) {
+            (&Some(ref file), Some(line), Some(col)) => write!(f, " at {}:{}:{}", file, line, col),
+            (&Some(ref file), _, _)                  => write!(f, " at {}", file),
+            _                                        => Ok(()),
+        }
+    }
+}
+
+/// The scheme stack of a throw, along with the rust stack that caught it
+///
+/// Displays as one trace, scheme frames first as they are the innermost. The rust part
+/// follows the usual `RUST_BACKTRACE` setting.
+#[derive(Clone, Debug)]
+pub struct Backtrace {
+    /// Scheme frames, innermost first
+    pub frames: Vec<Frame>,
+    rust: Arc<RustBacktrace>,
+}
+
+impl Backtrace {
+    /// The rust stack at the point the throw was caught
+    #[inline]
+    pub fn rust(&self) -> &RustBacktrace { &self.rust }
+}
+
+// rust backtraces can't be compared, only the scheme side is
+impl PartialEq for Backtrace {
+    fn eq(&self, other: &Backtrace) -> bool { self.frames == other.frames }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "scheme backtrace:")?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "{:4}: {}", i, frame)?;
+        }
+
+        match self.rust.status() {
+            BacktraceStatus::Captured => write!(f, "rust backtrace:\n{}", self.rust),
+            _ => write!(f, "rust backtrace: disabled, set RUST_BACKTRACE=1 to capture it"),
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! Rust functions callable from scheme
+//!
+//! Any rust function or closure whose arguments implement [FnArg](trait.FnArg.html) and
+//! whose return type implements [FnRet](trait.FnRet.html) can be defined as a scheme
+//! procedure:
+//!
+//! ```rust,ignore
+//! Guile::define_fn(ctx, "greet", "Greet someone, loudly or not.",
+//!     |name: String, loud: Option<bool>, rest: Rest<i32>| -> Result<String, GuileError> {
+//!         if rest.len() > 2 { return Err("too many numbers".into()); }
+//!         Ok(if loud == Some(true) { format!("HELLO {}!", name) } else { format!("hello {}", name) })
+//!     });
+//!
+//! // (greet "you")           => "hello you"
+//! // (greet "you" #t 1 2)    => "HELLO you!"
+//! // (greet 5)               => wrong-type-arg error
+//! ```
+//!
+//! A `GuileCtx` argument is passed the context of the call and takes no scheme argument.
+//! Functions returning `Scm` values have to be `fn` items (like `fn f<'g>(GuileCtx<'g>) ->
+//! Scm<'g, Int>`), a closure can't tie its return type to the lifetime of its argument.
+
+use libc;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem::transmute;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, TryLockError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use scm::*;
+use scm::String as ScmString;
+use interp::{self, Guile, GuileCtx};
+use error::GuileError;
+use unwind;
+
+use guile_rs_sys::*;
+
+
+/// How an argument is taken from the scheme call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    /// Not a scheme argument, the `GuileCtx` of the call
+    Context,
+    Required,
+    /// Missing arguments are `None`
+    Optional,
+    /// All the remaining arguments
+    Rest,
+}
+
+/// Arguments of a scheme call, consumed in order by `FnArg::take`
+pub struct Args<'g> {
+    ctx:   GuileCtx<'g>,
+    name:  Option<&'g str>,
+    // marks missing optional arguments
+    none:  SCM,
+    fixed: SCM,
+    rest:  SCM,
+    pos:   usize,
+}
+
+impl<'g> Args<'g> {
+    #[inline]
+    pub fn ctx(&self) -> GuileCtx<'g> { self.ctx }
+
+    /// The next fixed argument, `None` for a missing optional one
+    pub fn next(&mut self) -> Option<Scm<'g, Untyped>> {
+        self.pos += 1;
+        unsafe {
+            if gu_scm_is_pair(self.fixed) != 1 {
+                return None;
+            }
+            let arg = gu_scm_car(self.fixed);
+            self.fixed = gu_scm_cdr(self.fixed);
+
+            if gu_scm_is_eq(arg, self.none) { None } else { Some(Scm::_from_raw(arg)) }
+        }
+    }
+
+    /// All remaining arguments
+    pub fn rest(&mut self) -> Vec<Scm<'g, Untyped>> {
+        let mut v = Vec::new();
+        unsafe {
+            while gu_scm_is_pair(self.rest) == 1 {
+                v.push(Scm::_from_raw(gu_scm_car(self.rest)));
+                self.rest = gu_scm_cdr(self.rest);
+            }
+        }
+        v
+    }
+
+    /// Error for the argument last taken, `value` not being a `expected`
+    pub fn wrong_type<TS: TypeSpec>(&self, value: &Scm<'g, TS>, expected: &str) -> GuileError {
+        let mut err = GuileError::wrong_type("", value);
+        err.subr = self.name.map(|n| n.to_string());
+        err.message = format!("Wrong type argument in position {} (expecting {}): {}",
+                              self.pos, expected, err.irritants[0]);
+        err
+    }
+}
+
+/// A rust argument of a function defined with `Guile::define_fn`
+pub trait FnArg<'g>: Sized {
+    fn kind() -> ArgKind;
+
+    /// Convert the next argument(s), or explain why they don't convert
+    fn take(args: &mut Args<'g>) -> Result<Self, GuileError>;
+}
+
+impl<'g, T: FromScm<'g>> FnArg<'g> for T {
+    #[inline]
+    fn kind() -> ArgKind { ArgKind::Required }
+
+    fn take(args: &mut Args<'g>) -> Result<T, GuileError> {
+        // arity is checked by scheme, required ones are always there
+        let arg = args.next().unwrap();
+        T::from_scm(Scm::_from_raw(arg.data)).ok_or_else(|| args.wrong_type(&arg, T::type_name()))
+    }
+}
+
+impl<'g, T: FromScm<'g>> FnArg<'g> for Option<T> {
+    #[inline]
+    fn kind() -> ArgKind { ArgKind::Optional }
+
+    fn take(args: &mut Args<'g>) -> Result<Option<T>, GuileError> {
+        match args.next() {
+            Some(arg) => T::from_scm(Scm::_from_raw(arg.data))
+                             .map(Some)
+                             .ok_or_else(|| args.wrong_type(&arg, T::type_name())),
+            None      => Ok(None),
+        }
+    }
+}
+
+impl<'g> FnArg<'g> for GuileCtx<'g> {
+    #[inline]
+    fn kind() -> ArgKind { ArgKind::Context }
+
+    #[inline]
+    fn take(args: &mut Args<'g>) -> Result<GuileCtx<'g>, GuileError> { Ok(args.ctx()) }
+}
+
+/// The remaining arguments of a call, each converted to `T`
+#[derive(Debug)]
+pub struct Rest<T>(pub Vec<T>);
+
+impl<T> Deref for Rest<T> {
+    type Target = Vec<T>;
+
+    #[inline]
+    fn deref(&self) -> &Vec<T> { &self.0 }
+}
+
+impl<T> DerefMut for Rest<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Vec<T> { &mut self.0 }
+}
+
+impl<'g, T: FromScm<'g>> FnArg<'g> for Rest<T> {
+    #[inline]
+    fn kind() -> ArgKind { ArgKind::Rest }
+
+    fn take(args: &mut Args<'g>) -> Result<Rest<T>, GuileError> {
+        let mut v = Vec::new();
+        for arg in args.rest() {
+            args.pos += 1;
+            match T::from_scm(Scm::_from_raw(arg.data)) {
+                Some(a) => v.push(a),
+                None    => return Err(args.wrong_type(&arg, T::type_name())),
+            }
+        }
+        Ok(Rest(v))
+    }
+}
+
+/// A rust return value of a function defined with `Guile::define_fn`
+///
+/// `Err` values of a `Result` are thrown, keeping the key of a `GuileError`.
+pub trait FnRet<'g> {
+    fn into_ret(self, ctx: GuileCtx<'g>) -> Result<Scm<'g, Untyped>, GuileError>;
+}
+
+impl<'g> FnRet<'g> for () {
+    #[inline]
+    fn into_ret(self, _ctx: GuileCtx<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Ok(Scm::_from_raw(unsafe { gu_SCM_UNSPECIFIED() }))
+    }
+}
+
+impl<'g, TS: TypeSpec> FnRet<'g> for Scm<'g, TS> {
+    #[inline]
+    fn into_ret(self, _ctx: GuileCtx<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Ok(self.into_unspecified())
+    }
+}
+
+impl<'g, T: FnRet<'g>, E: Into<GuileError>> FnRet<'g> for Result<T, E> {
+    #[inline]
+    fn into_ret(self, ctx: GuileCtx<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+        self.map_err(Into::into).and_then(|r| r.into_ret(ctx))
+    }
+}
+
+// returned values convert like procedure arguments
+macro_rules! simple_ret {
+    ($ty:ty, $spec:ty) => {
+        impl<'g> FnRet<'g> for $ty {
+            #[inline]
+            fn into_ret(self, ctx: GuileCtx<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+                Ok(self.into_arg(ctx))
+            }
+        }
+    }
+}
+
+simple_to_scm_types!(simple_ret);
+simple_ret!(bool, Bool);
+
+/// A rust function callable from scheme, implemented for functions of up to 8 arguments
+///
+/// Any guile thread may call the procedure, concurrently too, so the function is shared
+/// between threads.
+pub trait GuileFn<'g, A, R>: Send + Sync + 'static {
+    /// Kinds of the arguments, in order
+    fn kinds() -> Vec<ArgKind>;
+
+    fn call_with(&self, args: &mut Args<'g>) -> Result<Scm<'g, Untyped>, GuileError>;
+}
+
+/// Like [GuileFn](trait.GuileFn.html), for functions with mutable state
+pub trait GuileFnMut<'g, A, R>: Send + 'static {
+    fn kinds() -> Vec<ArgKind>;
+
+    fn call_with_mut(&mut self, args: &mut Args<'g>) -> Result<Scm<'g, Untyped>, GuileError>;
+}
+
+macro_rules! guile_fn_impl {
+    ($($a:ident),*) => {
+        impl<'g, F, R, $($a),*> GuileFn<'g, ($($a,)*), R> for F
+            where F: Fn($($a),*) -> R + Send + Sync + 'static, R: FnRet<'g>, $($a: FnArg<'g>),* {
+            #[inline]
+            fn kinds() -> Vec<ArgKind> { vec![$($a::kind()),*] }
+
+            #[allow(non_snake_case, unused_variables)]
+            fn call_with(&self, args: &mut Args<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+                $(let $a = $a::take(args)?;)*
+                let ctx = args.ctx();
+                (self)($($a),*).into_ret(ctx)
+            }
+        }
+
+        impl<'g, F, R, $($a),*> GuileFnMut<'g, ($($a,)*), R> for F
+            where F: FnMut($($a),*) -> R + Send + 'static, R: FnRet<'g>, $($a: FnArg<'g>),* {
+            #[inline]
+            fn kinds() -> Vec<ArgKind> { vec![$($a::kind()),*] }
+
+            #[allow(non_snake_case, unused_variables)]
+            fn call_with_mut(&mut self, args: &mut Args<'g>) -> Result<Scm<'g, Untyped>, GuileError> {
+                $(let $a = $a::take(args)?;)*
+                let ctx = args.ctx();
+                (self)($($a),*).into_ret(ctx)
+            }
+        }
+    }
+}
+
+guile_fn_impl!();
+guile_fn_impl!(A1);
+guile_fn_impl!(A1, A2);
+guile_fn_impl!(A1, A2, A3);
+guile_fn_impl!(A1, A2, A3, A4);
+guile_fn_impl!(A1, A2, A3, A4, A5);
+guile_fn_impl!(A1, A2, A3, A4, A5, A6);
+guile_fn_impl!(A1, A2, A3, A4, A5, A6, A7);
+guile_fn_impl!(A1, A2, A3, A4, A5, A6, A7, A8);
+
+/// Scheme arity of a function: required and optional counts, and whether it takes a rest
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arity {
+    pub required: usize,
+    pub optional: usize,
+    pub rest:     bool,
+}
+
+impl Arity {
+    /// Arity of argument kinds, panics when they are not in required, optional, rest order
+    pub fn from_kinds(kinds: &[ArgKind]) -> Arity {
+        let mut arity = Arity { required: 0, optional: 0, rest: false };
+        for kind in kinds {
+            match *kind {
+                ArgKind::Context  => {},
+                ArgKind::Required => {
+                    assert!(arity.optional == 0 && !arity.rest, "required argument after an optional or rest one");
+                    arity.required += 1;
+                },
+                ArgKind::Optional => {
+                    assert!(!arity.rest, "optional argument after a rest one");
+                    arity.optional += 1;
+                },
+                ArgKind::Rest     => {
+                    assert!(!arity.rest, "more than one rest argument");
+                    arity.rest = true;
+                },
+            }
+        }
+        arity
+    }
+}
+
+// type erased function behind a scheme procedure
+trait Call {
+    unsafe fn call(&self, none: SCM, fixed: SCM, rest: SCM) -> Result<SCM, GuileError>;
+}
+
+struct Primitive<'g, F, A, R> {
+    name: Option<::std::string::String>,
+    fun:  F,
+    sig:  PhantomData<fn(A) -> R>,
+    ctx:  PhantomData<GuileCtx<'g>>,
+}
+
+impl<'g, F, A, R> Primitive<'g, F, A, R> {
+    fn new(name: Option<&str>, fun: F) -> Primitive<'g, F, A, R> {
+        Primitive {
+            name: name.map(|n| n.to_string()),
+            fun,
+            sig:  PhantomData,
+            ctx:  PhantomData,
+        }
+    }
+
+    unsafe fn args(&self, none: SCM, fixed: SCM, rest: SCM) -> Args<'g> {
+        // `name` lives as long as the procedure, which outlives the call
+        let name: Option<&'g str> = self.name.as_ref().map(|n| transmute(n.as_str()));
+        Args { ctx: GuileCtx::new(), name, none, fixed, rest, pos: 0 }
+    }
+}
+
+impl<'g, F: GuileFn<'g, A, R>, A, R> Call for Primitive<'g, F, A, R> {
+    unsafe fn call(&self, none: SCM, fixed: SCM, rest: SCM) -> Result<SCM, GuileError> {
+        let mut args = self.args(none, fixed, rest);
+        self.fun.call_with(&mut args).map(|r| r.data)
+    }
+}
+
+thread_local! {
+    // the `GuileFnMut` closures running on this thread, by address
+    static RUNNING: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+// a `GuileFnMut`, its state locked for each call as any thread may call it
+struct PrimitiveMut<'g, F, A, R>(Primitive<'g, Mutex<F>, A, R>);
+
+impl<'g, F: GuileFnMut<'g, A, R>, A, R> Call for PrimitiveMut<'g, F, A, R> {
+    unsafe fn call(&self, none: SCM, fixed: SCM, rest: SCM) -> Result<SCM, GuileError> {
+        let mut args = self.0.args(none, fixed, rest);
+        let id = &self.0.fun as *const Mutex<F> as usize;
+
+        let mut fun = match self.0.fun.try_lock() {
+            Ok(fun) => fun,
+            // a closure calling itself back through scheme would deadlock, or alias its state
+            Err(TryLockError::WouldBlock) if RUNNING.with(|r| r.borrow().contains(&id)) =>
+                return Err(GuileError::new("rust-error", "FnMut closure called while already running")),
+            // another thread is running it
+            Err(TryLockError::WouldBlock) => self.0.fun.lock().unwrap_or_else(|e| e.into_inner()),
+            // a panicking call has been reported already
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        };
+
+        RUNNING.with(|r| r.borrow_mut().push(id));
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| fun.call_with_mut(&mut args)));
+        RUNNING.with(|r| r.borrow_mut().retain(|&r| r != id));
+
+        match ret {
+            Ok(ret) => ret.map(|r| r.data),
+            Err(p)  => panic::resume_unwind(p),
+        }
+    }
+}
+
+// the single gsubr behind every rust procedure
+unsafe extern "C" fn rust_call(data: SCM, none: SCM, fixed: SCM, rest: SCM) -> SCM {
+    let ret = {
+        let prim: &Box<dyn Call> = &*(scm_to_pointer(data) as *const Box<dyn Call>);
+        match panic::catch_unwind(AssertUnwindSafe(|| prim.call(none, fixed, rest))) {
+            Ok(r)  => r,
+            // resumed once the throw reaches rust again
+            Err(p) => unwind::throw_panic("panic in a rust procedure", p),
+        }
+    };
+
+    match ret {
+        Ok(r)    => r,
+        Err(err) => {
+            // nothing owned by rust may be left when the throw jumps over this frame
+            let (key, subr, msg, args) = err.into_raw();
+            scm_error_scm(key, subr, msg, args, gu_SCM_BOOL_F());
+            gu_SCM_UNSPECIFIED()
+        },
+    }
+}
+
+unsafe extern "C" fn drop_call(data: *mut libc::c_void) {
+    // finalizers run from the GC
+    unwind::catch_deferred(|| drop(Box::from_raw(data as *mut Box<dyn Call>)));
+}
+
+// builds the procedure around `rust_call`, with the arity and properties of the function
+fn wrapper_source(arity: Arity) -> ::std::string::String {
+    let required: Vec<_> = (0..arity.required).map(|i| format!("a{}", i)).collect();
+    let optional: Vec<_> = (0..arity.optional).map(|i| format!("o{}", i)).collect();
+
+    let mut formals = required.join(" ");
+    if !optional.is_empty() {
+        formals += " #:optional ";
+        formals += &optional.iter().map(|o| format!("({} %none)", o)).collect::<Vec<_>>().join(" ");
+    }
+    if arity.rest {
+        formals += " #:rest %rest";
+    }
+
+    let fixed: Vec<_> = required.iter().chain(optional.iter()).cloned().collect();
+    format!("
+(lambda (%call %data %name %doc)
+  (let* ((%none (list 'none))
+         (proc  (lambda* ({}) (%call %data %none (list {}) {}))))
+    (if %name (set-procedure-property! proc 'name %name))
+    (if %doc  (set-procedure-property! proc 'documentation %doc))
+    proc))", formals, fixed.join(" "), if arity.rest { "%rest" } else { "'()" })
+}
+
+// the `%rust-call` gsubr, and the wrapper makers by arity, made once and kept protected
+static RUST_CALL: AtomicUsize = AtomicUsize::new(0);
+static WRAPPERS: Mutex<Vec<(Arity, usize)>> = Mutex::new(Vec::new());
+
+// only call in guile mode
+unsafe fn rust_call_gsubr() -> SCM {
+    let cached = RUST_CALL.load(Ordering::Acquire);
+    if cached != 0 {
+        return cached as SCM;
+    }
+
+    let made = scm_gc_protect_object(scm_c_make_gsubr(CString::new("%rust-call").unwrap().as_ptr(), 4, 0, 0,
+                                                      rust_call as *mut libc::c_void));
+    // another thread may have made it meanwhile, keep the first one
+    match RUST_CALL.compare_exchange(0, made as usize, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_)      => made,
+        Err(first) => {
+            scm_gc_unprotect_object(made);
+            first as SCM
+        },
+    }
+}
+
+// the wrapper maker for `arity`, evaluated in `(guile)` so the current module can't break it
+// only call in guile mode
+unsafe fn wrapper(arity: Arity) -> SCM {
+    if let Some(&(_, make)) = WRAPPERS.lock().unwrap_or_else(|e| e.into_inner()).iter().find(|w| w.0 == arity) {
+        return make as SCM;
+    }
+
+    // evaluated unlocked, the evaluation may call back into rust
+    let made = scm_gc_protect_object(interp::eval_in_root(&wrapper_source(arity)));
+
+    let mut wrappers = WRAPPERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(&(_, make)) = wrappers.iter().find(|w| w.0 == arity) {
+        scm_gc_unprotect_object(made);
+        return make as SCM;
+    }
+    wrappers.push((arity, made as usize));
+    made
+}
+
+// a scheme procedure owning `prim`, which is dropped once the GC collects it
+fn make_procedure<'g>(ctx: GuileCtx<'g>, name: Option<&str>, doc: Option<&str>,
+                      arity: Arity, prim: Box<dyn Call + 'g>) -> Scm<'g, Procedure> {
+    // only the type parameters carry `'g`, the function itself is `'static`
+    let prim: Box<dyn Call> = unsafe { transmute(prim) };
+
+    unsafe {
+        let data = scm_from_pointer(Box::into_raw(Box::new(prim)) as *mut libc::c_void, Some(drop_call));
+
+        let name = name.map(|n| Scm::<Symbol>::from_str(ctx, n).data).unwrap_or(gu_SCM_BOOL_F());
+        let doc  = doc.map(|d| Scm::<ScmString>::from(ctx, d).data).unwrap_or(gu_SCM_BOOL_F());
+
+        Scm::_from_raw(scm_call_4(wrapper(arity), rust_call_gsubr(), data, name, doc))
+    }
+}
+
+impl<'g> Scm<'g, Procedure> {
+    /// A scheme procedure calling the closure `fun`
+    ///
+    /// The closure is owned by the procedure and dropped when the GC collects it, possibly
+    /// on another thread. Its arguments and arity work as in `Guile::define_fn`.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let buffer = Arc::new(Mutex::new(Buffer::new()));
+    /// let insert = Scm::<Procedure>::from_closure(ctx, move |text: String| {
+    ///     buffer.lock().unwrap().insert(&text);
+    /// });
+    /// ```
+    pub fn from_closure<F, A, R>(ctx: GuileCtx<'g>, fun: F) -> Scm<'g, Procedure>
+        where F: GuileFn<'g, A, R>, A: 'g, R: 'g {
+        make_procedure(ctx, None, None, Arity::from_kinds(&F::kinds()), Box::new(Primitive::new(None, fun)))
+    }
+
+    /// Like `from_closure`, for closures mutating their state
+    ///
+    /// Calls from other threads wait for the running one to return. A call re-entering the
+    /// closure while it runs (through scheme) throws a `rust-error` instead.
+    pub fn from_closure_mut<F, A, R>(ctx: GuileCtx<'g>, fun: F) -> Scm<'g, Procedure>
+        where F: GuileFnMut<'g, A, R>, A: 'g, R: 'g {
+        let prim = PrimitiveMut(Primitive::new(None, Mutex::new(fun)));
+        make_procedure(ctx, None, None, Arity::from_kinds(&F::kinds()), Box::new(prim))
+    }
+}
+
+impl Guile {
+    /// Make a scheme procedure calling `fun`, see the [module documentation](index.html)
+    ///
+    /// Arguments that do not convert throw `wrong-type-arg`, `Err` results are thrown
+    /// and a panic is thrown as `rust-panic`, to be resumed when the throw reaches rust.
+    pub fn make_fn<'g, F, A, R>(ctx: GuileCtx<'g>, name: &str, doc: &str, fun: F) -> Scm<'g, Procedure>
+        where F: GuileFn<'g, A, R>, A: 'g, R: 'g {
+        let arity = Arity::from_kinds(&F::kinds());
+        make_procedure(ctx, Some(name), Some(doc), arity, Box::new(Primitive::new(Some(name), fun)))
+    }
+
+    /// Like `make_fn`, also defining the procedure as `name` in the current module
+    pub fn define_fn<'g, F, A, R>(ctx: GuileCtx<'g>, name: &str, doc: &str, fun: F) -> Scm<'g, Procedure>
+        where F: GuileFn<'g, A, R>, A: 'g, R: 'g {
+        let proc_ = Self::make_fn(ctx, name, doc, fun);
+        unsafe { scm_c_define(CString::new(name).unwrap().as_ptr(), proc_.data); }
+        proc_
+    }
+}
@@ -3,46 +3,169 @@ extern crate libc;
 
 use self::guile_rs_sys::*;
 use std::ptr;
+use std::cell::Cell;
 use std::ffi::CString;
+use std::marker::PhantomData;
 use std::mem::{transmute};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use scm::Scm;
-use scm::{Untyped, TypeSpec, Symbol, List};
+use scm::{Untyped, TypeSpec, Symbol, Module, FromScm};
+use error::{GuileError, Frame};
+use unwind::{self, Callback};
+use runtime;
 
 #[macro_export]
 macro_rules! scm_eval {
-    {$($tts:tt)*} => {
+    {$ctx:expr, $($tts:tt)*} => {
         {
-            $crate::interp::Guile::eval(stringify!(
+            $crate::interp::Guile::eval($ctx, stringify!(
                 $($tts)*
             ))
         }
     }
 }
 
+/// Proof that the current thread is in guile mode
+///
+/// A context is handed to the closure of `Guile::call_with_guile` and is required by every
+/// constructor of `Scm` values. Values carry the context's `'g` lifetime, so they cannot
+/// escape the closure and be used after guile mode has ended.
+///
+/// Guile mode is per thread, so contexts are neither `Send` nor `Sync`.
+#[derive(Clone, Copy, Debug)]
+pub struct GuileCtx<'g> {
+    _scope: PhantomData<&'g ()>,
+    _thread: PhantomData<*mut ()>,
+}
+
+impl<'g> GuileCtx<'g> {
+    // only valid while the current thread is in guile mode
+    #[inline]
+    pub(crate) unsafe fn new() -> GuileCtx<'g> {
+        GuileCtx { _scope: PhantomData, _thread: PhantomData }
+    }
+}
+
+thread_local! {
+    // number of active guile mode entries on this thread
+    static GUILE_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Marks the current thread as being in guile mode for as long as it lives
+pub(crate) struct GuileModeGuard {
+    depth: usize,
+}
+
+impl GuileModeGuard {
+    pub(crate) fn enter() -> GuileModeGuard {
+        let depth = GUILE_DEPTH.with(|d| d.replace(d.get() + 1));
+        GuileModeGuard { depth }
+    }
+}
+
+impl Drop for GuileModeGuard {
+    fn drop(&mut self) {
+        GUILE_DEPTH.with(|d| d.set(self.depth));
+    }
+}
+
+/// Evaluate scheme code of the crate in `(guile)`, only call in guile mode
+pub(crate) unsafe fn eval_in_root(src: &str) -> SCM {
+    let src = CString::new(src).unwrap();
+    Guile::try_call(|| scm_c_eval_string_in_module(src.as_ptr(), scm_the_root_module()))
+        .expect("scheme code of guile-rs failed to evaluate")
+}
+
+/// A scheme procedure (or other value) of the crate, evaluated once in `(guile)` and shared
+/// by all threads
+///
+/// Evaluating in the root module rather than the current one keeps it working whatever
+/// the current module binds, a sandbox or a module shadowing `lambda` included.
+pub(crate) struct RootProc {
+    src:   &'static str,
+    proc_: AtomicUsize,
+}
+
+impl RootProc {
+    pub(crate) const fn new(src: &'static str) -> RootProc {
+        RootProc { src, proc_: AtomicUsize::new(0) }
+    }
+
+    /// The procedure, only call in guile mode
+    pub(crate) unsafe fn get(&self) -> SCM {
+        let cached = self.proc_.load(Ordering::Acquire);
+        if cached != 0 {
+            return cached as SCM;
+        }
+
+        let made = scm_gc_protect_object(eval_in_root(self.src));
+
+        // another thread may have made it meanwhile, keep the first one
+        match self.proc_.compare_exchange(0, made as usize, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_)      => made,
+            Err(first) => {
+                scm_gc_unprotect_object(made);
+                first as SCM
+            },
+        }
+    }
+}
+
 pub struct Guile {
 }
 
 impl Guile {
-    unsafe extern "C" fn proxy_guile_function<F: FnOnce(A)->R, A, R>(data: *mut libc::c_void) -> *mut libc::c_void {
-        // read is highly unsafe!
-        let data: (F, A) = ptr::read(transmute(data));
-        let (fun, args) = data;
+    unsafe extern "C" fn proxy_guile_function<F: FnOnce() -> R, R>(data: *mut libc::c_void) -> *mut libc::c_void {
+        // the result is stored in the callback, owned by `call_with_guile`
+        Callback::<F, R>::run(data);
+        ptr::null_mut()
+    }
 
-        let ret: *mut R = &mut (fun)(args);
-        transmute::<*mut R, *mut libc::c_void>(ret)
+    /// Check whether the current thread was put in guile mode by this crate
+    #[inline]
+    pub fn in_guile_mode() -> bool {
+        GUILE_DEPTH.with(|d| d.get() > 0)
     }
 
-    pub fn call_with_guile<F: FnOnce(A)->R, A, R>(fun: F, args: A) -> R {
+    /// Run `fun` in guile mode
+    ///
+    /// Threads are registered with guile on their first call, later and nested calls reuse
+    /// the thread's guile mode. Scheme throws not caught inside `fun` stop at the boundary
+    /// of this call (a continuation barrier) and make it panic.
+    ///
+    /// A panic inside `fun` is caught before reaching libguile and resumed once back here.
+    pub fn call_with_guile<F, A, R>(fun: F, args: A) -> R
+        where F: for<'g> FnOnce(GuileCtx<'g>, A) -> R {
+        // restored here rather than in the proxy, guile may unwind over it
+        let _guard = GuileModeGuard::enter();
+        let nested = GUILE_DEPTH.with(|d| d.get() > 1);
+
+        let mut callback = Callback::new(move || {
+            let ctx = unsafe { GuileCtx::new() };
+            runtime::enter_thread(ctx);
+            // panics of rust procedures called by `fun` come back as a `rust-panic` throw
+            let key = unsafe { scm_from_utf8_symbol(CString::new("rust-panic").unwrap().as_ptr()) };
+            unsafe { Self::catch_raw(key, || fun(ctx, args)) }
+                .unwrap_or_else(|_| panic!("uncaught scheme throw in call_with_guile"))
+        });
         unsafe {
-            let args_pt: *mut (F, A) = &mut (fun, args);
-            let args_pt = args_pt as *mut libc::c_void;
+            if nested {
+                // already in guile mode, only keep the barrier
+                scm_c_with_continuation_barrier(Some(Self::proxy_fn(&callback)), callback.as_ptr());
+            } else {
+                scm_with_guile(Some(Self::proxy_fn(&callback)), callback.as_ptr());
+            }
+        }
 
-            let ret = scm_with_guile(Some(Self::proxy_guile_function::<F, A, R>), args_pt);
+        callback.into_result().expect("uncaught scheme throw in call_with_guile")
+    }
 
-            // read = very unsafe!
-            ptr::read(transmute::<*mut libc::c_void, *mut R>(ret))
-        }
+    // names the proxy's closure type, which can't be written out
+    #[inline]
+    fn proxy_fn<F: FnOnce() -> R, R>(_: &Callback<F, R>) -> unsafe extern "C" fn(*mut libc::c_void) -> *mut libc::c_void {
+        Self::proxy_guile_function::<F, R>
     }
 
     unsafe extern "C" fn catch_handler(data: *mut libc::c_void, key: SCM, args: SCM) -> SCM {
@@ -56,55 +179,145 @@ impl Guile {
         gu_SCM_UNDEFINED()
     }
 
-    unsafe extern "C" fn caught_body<F: FnOnce(A)->Scm<TS>, A, TS: TypeSpec>(data: *mut libc::c_void) -> SCM {
-        let data: (F, A) = ptr::read(transmute(data));
-        let (fun, args) = data;
+    unsafe extern "C" fn caught_body<F: FnOnce() -> R, R>(data: *mut libc::c_void) -> SCM {
+        // the result is stored in the callback, owned by `_call_with_catch`
+        Callback::<F, R>::run(data);
+        gu_SCM_UNDEFINED()
+    }
 
-        (fun)(args).into_raw()
+    #[inline]
+    fn caught_body_fn<F: FnOnce() -> R, R>(_: &Callback<F, R>) -> unsafe extern "C" fn(*mut libc::c_void) -> SCM {
+        Self::caught_body::<F, R>
     }
 
-    fn _call_with_catch<TS: TypeSpec, RT: TypeSpec, F: FnOnce(A)->Scm<RT>, A>
-        (key: Scm<TS>, body: F, body_args: A) -> Result<Scm<RT>, (Scm<Symbol>, Scm<List>)> {
-            assert!(key.is_true() || key.is_symbol());
+    // records the scheme stack of a throw, called before the stack unwinds
+    unsafe extern "C" fn stack_handler(data: *mut libc::c_void, _key: SCM, _args: SCM) -> SCM {
+        let frames: *mut Option<Vec<Frame>> = transmute(data);
+        // only the innermost throw, a rethrow keeps the original stack
+        if (*frames).is_none() {
+            // no unwinding from here, a panic comes back with the next callback result
+            unwind::catch_deferred(|| *frames = Some(Frame::capture()));
+        }
+
+        gu_SCM_UNDEFINED()
+    }
+
+    // run `body` under a catch for `key`, giving back the raw key and args of a throw
+    // only call in guile mode
+    pub(crate) unsafe fn catch_raw<F: FnOnce() -> R, R>(key: SCM, body: F) -> Result<R, (SCM, SCM)> {
+        Self::catch_stack(key, body, None)
+    }
+
+    // like `catch_raw`, also recording the stack of a throw into `frames` when given
+    unsafe fn catch_stack<F: FnOnce() -> R, R>(key: SCM, body: F, frames: Option<&mut Option<Vec<Frame>>>)
+        -> Result<R, (SCM, SCM)> {
+        let mut callback = Callback::new(body);
+
+        let mut err_data: (bool, SCM, SCM) = (false, gu_SCM_UNDEFINED(), gu_SCM_UNDEFINED());
+        let err_data_ptr: *mut (bool, SCM, SCM) = &mut err_data;
+        let err_data_ptr: *mut libc::c_void = transmute(err_data_ptr);
+
+        let (pre_handler, frames_ptr): (scm_t_catch_handler, *mut libc::c_void) = match frames {
+            Some(f) => (Some(Self::stack_handler), transmute(f as *mut Option<Vec<Frame>>)),
+            None    => (None, ptr::null_mut()),
+        };
 
-            unsafe {
-                let body_args_pt: *mut (F, A) = &mut (body, body_args);
-                let body_args_pt = body_args_pt as *mut libc::c_void;
-
-                let mut err_data: (bool, SCM, SCM) = (false, gu_SCM_UNDEFINED(), gu_SCM_UNDEFINED());
-                let err_data_ptr: *mut (bool, SCM, SCM) = &mut err_data;
-                let err_data_ptr: *mut libc::c_void = transmute(err_data_ptr);
-
-                let ret: SCM = scm_internal_catch(key.data,
-                                                  Some(Self::caught_body::<F, A, RT>), // lol
-                                                  body_args_pt,
-                                                  Some(Self::catch_handler),
-                                                  err_data_ptr);
-                if err_data.0 {
-                    // NOTE: not sure if always these types...
-                    // instead of assuming, we check before rewraping for now
-                    // NOTE: not sure if taking the key and args out of the scope of the handler is
-                    // a good idea...
-                    Err((Scm::<Untyped>::from_raw(err_data.1).into_symbol().unwrap(),
-                         Scm::<Untyped>::from_raw(err_data.2).into_list().unwrap()))
-                } else {
-                    Ok(Scm::<RT>::_from_raw(ret))
-                }
+        scm_c_catch(key,
+                    Some(Self::caught_body_fn(&callback)),
+                    callback.as_ptr(),
+                    Some(Self::catch_handler),
+                    err_data_ptr,
+                    pre_handler,
+                    frames_ptr);
+
+        // resumes a panic of the body
+        let ret = callback.into_result();
+
+        if err_data.0 {
+            // a panic of rust called by the body, thrown up to here
+            if let Some(p) = unwind::take_thrown(err_data.1, err_data.2) {
+                panic::resume_unwind(p);
             }
+            Err((err_data.1, err_data.2))
+        } else {
+            Ok(ret.expect("catch body returned without a result"))
+        }
     }
 
-    pub fn call_with_catch<RT: TypeSpec, F: FnOnce(A)->Scm<RT>, A>(key: Scm<Symbol>, body: F, body_args: A) -> Result<Scm<RT>, (Scm<Symbol>, Scm<List>)> {
+    /// Run `body` under a catch-all, returning a throw as a `GuileError`
+    ///
+    /// Backs the `try_` variants of the generated wrappers, only call in guile mode.
+    pub(crate) fn try_call<F: FnOnce() -> R, R>(body: F) -> Result<R, GuileError> {
+        unsafe {
+            Self::catch_raw(gu_SCM_BOOL_T(), body)
+                .map_err(|(key, args)| GuileError::from_raw(key, args))
+        }
+    }
+
+    // run `body` under a catch for `key`, with the backtrace of a throw in the error
+    pub(crate) fn catch_traced<F: FnOnce() -> R, R>(key: SCM, body: F) -> Result<R, GuileError> {
+        let mut frames = None;
+        unsafe {
+            Self::catch_stack(key, body, Some(&mut frames))
+                .map_err(|(key, args)| GuileError::from_raw(key, args)
+                                                  .with_backtrace(frames.unwrap_or_default()))
+        }
+    }
+
+    fn _call_with_catch<'g, TS: TypeSpec, RT: TypeSpec, F: FnOnce(A)->Scm<'g, RT>, A>
+        (key: Scm<'g, TS>, body: F, body_args: A) -> Result<Scm<'g, RT>, GuileError> {
+            assert!(key.is_true() || key.is_symbol());
+
+            Self::catch_traced(key.data, move || body(body_args))
+    }
+
+    pub fn call_with_catch<'g, RT: TypeSpec, F: FnOnce(A)->Scm<'g, RT>, A>(key: Scm<'g, Symbol>, body: F, body_args: A) -> Result<Scm<'g, RT>, GuileError> {
         Self::_call_with_catch(key, body, body_args)
     }
 
-    pub fn call_with_catch_all<RT: TypeSpec, F: FnOnce(A)->Scm<RT>, A>(body: F, body_args: A) -> Result<Scm<RT>, (Scm<Symbol>, Scm<List>)> {
-        Self::_call_with_catch(Scm::true_c(), body, body_args)
+    pub fn call_with_catch_all<'g, RT: TypeSpec, F: FnOnce(A)->Scm<'g, RT>, A>(ctx: GuileCtx<'g>, body: F, body_args: A) -> Result<Scm<'g, RT>, GuileError> {
+        Self::_call_with_catch(Scm::true_c(ctx), body, body_args)
     }
 
-    pub fn eval(s: &str) -> Scm<Untyped> {
+    pub fn eval<'g>(ctx: GuileCtx<'g>, s: &str) -> Scm<'g, Untyped> {
         let raw = unsafe {
             scm_c_eval_string(CString::new(s).unwrap().as_ptr())
         };
-        Scm::<Untyped>::from_raw(raw)
+        Scm::<Untyped>::from_raw(ctx, raw)
+    }
+
+    /// Evaluate `s`, returning a throw as a `GuileError` with its backtrace
+    pub fn try_eval<'g>(ctx: GuileCtx<'g>, s: &str) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch_traced(unsafe { gu_SCM_BOOL_T() }, || Self::eval(ctx, s))
+    }
+
+    /// Evaluate `s` with `module` as the top level
+    pub fn eval_in<'g>(module: &Scm<'g, Module>, s: &str) -> Scm<'g, Untyped> {
+        let s = CString::new(s).unwrap();
+        Scm::_from_raw(unsafe { scm_c_eval_string_in_module(s.as_ptr(), module.data) })
+    }
+
+    /// Like `eval_in`, returning a throw as a `GuileError` with its backtrace
+    pub fn try_eval_in<'g>(module: &Scm<'g, Module>, s: &str) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch_traced(unsafe { gu_SCM_BOOL_T() }, || Self::eval_in(module, s))
+    }
+
+    /// Evaluate an expression that has already been read, like `'(+ 1 2)`
+    pub fn eval_scm<'g, TS: TypeSpec>(expr: &Scm<'g, TS>, module: &Scm<'g, Module>) -> Scm<'g, Untyped> {
+        Scm::_from_raw(unsafe { scm_eval(expr.data, module.data) })
+    }
+
+    /// Like `eval_scm`, returning a throw as a `GuileError` with its backtrace
+    pub fn try_eval_scm<'g, TS: TypeSpec>(expr: &Scm<'g, TS>, module: &Scm<'g, Module>)
+        -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch_traced(unsafe { gu_SCM_BOOL_T() }, || Self::eval_scm(expr, module))
+    }
+
+    /// Evaluate `s` and convert the result (see [FromScm](../scm/trait.FromScm.html))
+    ///
+    /// Errors on a throw during evaluation or a result not convertible to `T`.
+    pub fn eval_as<'g, T: FromScm<'g>>(ctx: GuileCtx<'g>, s: &str) -> Result<T, GuileError> {
+        let value = Self::try_eval(ctx, s)?;
+        T::from_scm(Scm::_from_raw(value.data)).ok_or_else(|| GuileError::wrong_type("eval_as", &value))
     }
 }
@@ -0,0 +1,137 @@
+//! Interrupting evaluations from other threads
+//!
+//! An `InterruptHandle` is made on the thread that evaluates and handed to the one that
+//! decides to stop it. The interrupt throws into the evaluation at its next safe point,
+//! which comes back as an error with the `interrupted` key. The key is a symbol of its own,
+//! a `(throw 'interrupted)` of scheme code is not taken for an interrupt.
+//!
+//! # Example
+//! ```rust,ignore
+//! let handle = InterruptHandle::new(ctx);
+//! let stop = handle.clone();
+//! thread::spawn(move || { wait_for_ctrl_g(); stop.interrupt(); });
+//!
+//! match Guile::eval_interruptible(ctx, &handle, src) {
+//!     Err(ref e) if e.is_interrupted() => println!("quit"),
+//!     ...
+//! }
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use scm::{Scm, Untyped, SendScm};
+use interp::{Guile, GuileCtx, RootProc};
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+// (key . thrower), the thunk thrown into the interrupted thread throws the uninterned key
+const INTERRUPT: &str = "
+(let ((key (make-symbol \"interrupted\")))
+  (cons key (lambda () (scm-error key #f \"Evaluation interrupted\" '() #f))))";
+
+static INTERRUPT_PAIR: RootProc = RootProc::new(INTERRUPT);
+
+/// The key of interrupts, only call in guile mode
+pub(crate) unsafe fn interrupt_key() -> SCM {
+    gu_scm_car(INTERRUPT_PAIR.get())
+}
+
+/// Interrupts the evaluations of one thread, see `Guile::interruptible`
+///
+/// Handles can be cloned and sent to other threads. Interrupting while the thread is not in
+/// an interruptible evaluation does nothing.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    thread: SendScm<Untyped>,
+    // whether the thread is in `Guile::interruptible`, interrupts are sent under this lock
+    active: Mutex<bool>,
+}
+
+impl InterruptHandle {
+    /// A handle for the current thread
+    pub fn new(_ctx: GuileCtx) -> InterruptHandle {
+        InterruptHandle {
+            inner: Arc::new(Inner {
+                thread: SendScm::new(Scm::_from_raw(unsafe { scm_current_thread() })),
+                active: Mutex::new(false),
+            }),
+        }
+    }
+
+    /// Interrupt the evaluation running on the handle's thread, if any
+    ///
+    /// Returns whether there was one. Can be called from any thread, it enters guile mode
+    /// if needed.
+    pub fn interrupt(&self) -> bool {
+        let active = self.inner.active.lock().unwrap_or_else(|e| e.into_inner());
+        if *active {
+            Guile::call_with_guile(|ctx, inner| unsafe {
+                scm_system_async_mark_for_thread(gu_scm_cdr(INTERRUPT_PAIR.get()), inner.thread.get(ctx).data);
+            }, &*self.inner);
+        }
+        *active
+    }
+
+    // returns the previous state, for nested `Guile::interruptible`
+    fn set_active(&self, active: bool) -> bool {
+        let mut state = self.inner.active.lock().unwrap_or_else(|e| e.into_inner());
+        ::std::mem::replace(&mut *state, active)
+    }
+}
+
+impl Guile {
+    /// Run `body`, letting `handle` interrupt it
+    ///
+    /// An interrupt not caught inside `body` comes back as an error with the `interrupted`
+    /// key. `handle` has to be made on this thread. Nested in another `interruptible` with
+    /// the same handle, the outer one can still be interrupted once `body` returns.
+    pub fn interruptible<'g, F, R>(_ctx: GuileCtx<'g>, handle: &InterruptHandle, body: F) -> Result<R, GuileError>
+        where F: FnOnce() -> R {
+        let outer = handle.set_active(true);
+        let ret = Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, body);
+        handle.set_active(outer);
+
+        // an interrupt sent as the body ended is still pending, let it hit nothing, unless
+        // it is for the outer evaluation
+        if !outer {
+            let _ = Guile::try_call(|| unsafe { scm_async_tick(); });
+        }
+        ret
+    }
+
+    /// Evaluate `s`, letting `handle` interrupt it
+    pub fn eval_interruptible<'g>(ctx: GuileCtx<'g>, handle: &InterruptHandle, s: &str)
+        -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::interruptible(ctx, handle, || Self::eval(ctx, s))
+    }
+
+    /// Evaluate `s`, interrupting it if it takes longer than `timeout`
+    pub fn eval_with_timeout<'g>(ctx: GuileCtx<'g>, s: &str, timeout: Duration) -> Result<Scm<'g, Untyped>, GuileError> {
+        let handle = InterruptHandle::new(ctx);
+        let (done, wait) = mpsc::channel::<()>();
+
+        let timer = {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                // a disconnect means the evaluation is over
+                if let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                    handle.interrupt();
+                }
+            })
+        };
+
+        let ret = Self::eval_interruptible(ctx, &handle, s);
+        drop(done);
+        let _ = timer.join();
+        ret
+    }
+}
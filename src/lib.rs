@@ -7,15 +7,39 @@ extern crate lazy_static;
 
 #[macro_use]
 mod utils;
+mod unwind;
+pub mod error;
+pub mod dynwind;
+pub mod func;
+pub mod env;
+mod load;
+pub mod runtime;
+pub mod output;
+pub mod repl;
+pub mod rpc;
+pub mod interrupt;
+pub mod sandbox;
+pub mod plugin;
 
 #[macro_use]
 pub mod scm;
 #[macro_use]
 pub mod interp;
 
-pub use scm::{Scm, Untyped, Numeric, Bool, Int, TryAs};
+pub use scm::{Scm, Untyped, Numeric, Bool, Int, Procedure, Module, Variable, Port, Buffering, List, TryAs, IntoScm, FromScm, Rooted, RootScope, SendScm};
 pub use scm::String as ScmString;
-pub use interp::Guile;
+pub use interp::{Guile, GuileCtx};
+pub use error::{GuileError, Backtrace, Frame, Location};
+pub use dynwind::{DynwindFrame, RewindableFrame, Guard, Owned};
+pub use func::Rest;
+pub use env::Environment;
+pub use runtime::{GuileRuntime, RuntimeBuilder, WarningOutput, Version};
+pub use output::EvalOutput;
+pub use repl::{ReplServer, ReplAddress};
+pub use rpc::RpcServer;
+pub use interrupt::InterruptHandle;
+pub use sandbox::{Sandbox, SandboxBuilder, SandboxError, BindingSet};
+pub use plugin::{PluginManager, Plugin, PluginState};
 
 
 #[cfg(test)]
@@ -37,18 +61,42 @@ mod tests {
     //     TryAs
     // };
     pub use scm::*;
-    pub use interp::Guile;
+    pub use scm::String as ScmString;
+    pub use interp::{Guile, GuileCtx};
+    pub use error::GuileError;
+    pub use func::Rest;
+    pub use env::Environment;
+    pub use runtime::{GuileRuntime, WarningOutput, Version};
+    pub use repl::{ReplServer, ReplAddress};
+    pub use rpc::RpcServer;
+    pub use interrupt::InterruptHandle;
+    pub use sandbox::{Sandbox, SandboxError, BindingSet};
+    pub use plugin::{PluginManager, PluginState};
+
+    use std::string::String;
 
     use std::thread;
     use std::marker::PhantomData;
+    use std::cell::Cell;
+
+    // the earlier names of the type specs, guile_test is written with them
+    use scm::ForeignSpec as ForeignType;
+    type UnspecifiedSpec = Untyped;
+    type StringSpec = ScmString;
+    type IntSpec = Int;
+    type NumericSpec = Numeric;
+    #[allow(dead_code)]
+    type ForeignTypeSpec = Foreign;
+    #[allow(dead_code)]
+    type ForeignObjectSpec<FT> = ForeignObject<FT>;
 
     #[test]
     pub fn guile_test() {
-        let _ = Guile::call_with_guile(|_| {
+        let _ = Guile::call_with_guile(|ctx, _| {
             // Guile::eval("(display \"testing...\")");
 
             // DSL using stringify!() macro with eval
-            let s1: Scm<UnspecifiedSpec> = Guile::eval(stringify!(
+            let s1: Scm<UnspecifiedSpec> = Guile::eval(ctx, stringify!(
 
                     (display "test display...\n")
                     "test string..."
@@ -58,7 +106,7 @@ mod tests {
             let s: String                = s1.to_string();
             assert_eq!(s, "test string...");
 
-            let s2: Scm<StringSpec>      = Scm::from("test string...");
+            let s2: Scm<StringSpec>      = Scm::from(ctx, "test string...");
             let s: String                = s2.to_string();
             assert_eq!(s, "test string...");
 
@@ -67,12 +115,12 @@ mod tests {
             // let s = "string123".to_owned();
             // assert!(Guile::eval(&format!("\"{}\"", s)) == Scm::<StringSpec>::from_str(&s));
 
-            assert!(Scm::true_c().is_bool());   // is boolean scheme type
-            assert!(Scm::true_c().is_true());   // is true ...
-            assert!(Scm::false_c().is_false()); // ...
-            assert!(Scm::true_c().to_bool());   // as rust boolean type
+            assert!(Scm::true_c(ctx).is_bool());   // is boolean scheme type
+            assert!(Scm::true_c(ctx).is_true());   // is true ...
+            assert!(Scm::false_c(ctx).is_false()); // ...
+            assert!(Scm::true_c(ctx).to_bool());   // as rust boolean type
 
-            let v: Scm<IntSpec> = Scm::from(12345);
+            let v: Scm<IntSpec> = Scm::from(ctx, 12345);
             assert!(v.is_number());
             assert!(v.is_exact_integer());
             assert!(v.is_exact());
@@ -86,40 +134,40 @@ mod tests {
             // let n: i8 = v.try_as().unwrap();
 
             // s^2 + r = k
-            let (s, r) = Scm::from(10).exact_integer_sqrt().unwrap();
+            let (s, r) = Scm::from(ctx, 10).exact_integer_sqrt().unwrap();
             let (s, r): (i32, i32) = (s.try_as().unwrap(), r.try_as().unwrap());
             assert_eq!(s, 3);
             assert_eq!(r, 1);
 
-            assert!(Scm::from(90) == Scm::from(90));
-            assert!(!Scm::from(123).is_zero());
-            assert!(Scm::from(0).is_zero());
+            assert!(Scm::from(ctx, 90) == Scm::from(ctx, 90));
+            assert!(!Scm::from(ctx, 123).is_zero());
+            assert!(Scm::from(ctx, 0).is_zero());
 
-            assert!(Scm::from(123) >  Scm::from(90));
-            assert!(Scm::from(123) >= Scm::from(90));
-            assert!(Scm::from(90)  >= Scm::from(90));
+            assert!(Scm::from(ctx, 123) >  Scm::from(ctx, 90));
+            assert!(Scm::from(ctx, 123) >= Scm::from(ctx, 90));
+            assert!(Scm::from(ctx, 90)  >= Scm::from(ctx, 90));
 
             // Operations on numerics produce unspecified numeric type (NumericSpec)
-            let r: Scm<NumericSpec> = Scm::from(9) + Scm::from(8) * Scm::from(90) / (Scm::from(123) - Scm::from(113));
+            let r: Scm<NumericSpec> = Scm::from(ctx, 9) + Scm::from(ctx, 8) * Scm::from(ctx, 90) / (Scm::from(ctx, 123) - Scm::from(ctx, 113));
             let rr = 9 + 8 * 90 / (123 - 113);
-            assert!(Scm::from(rr) == r);
+            assert!(Scm::from(ctx, rr) == r);
 
-            let r = Scm::from(9);
-            assert!(r == Scm::from(9));
-            assert!(r.oneplus() == Scm::from(10));
-            assert!(r == Scm::from(9));
+            let r = Scm::from(ctx, 9);
+            assert!(r == Scm::from(ctx, 9));
+            assert!(r.oneplus() == Scm::from(ctx, 10));
+            assert!(r == Scm::from(ctx, 9));
 
-            assert!(Guile::call_with_catch("test".into(), |_| {
-                scm_eval!{ (throw 'test) }
+            assert!(Guile::call_with_catch(Scm::from(ctx, "test"), |_| {
+                scm_eval!{ ctx, (throw 'test) }
             }, ()).is_err());
 
-            assert!(Guile::call_with_catch_all(|_| {
-                scm_eval!{ (throw 'any) }
+            assert!(Guile::call_with_catch_all(ctx, |_| {
+                scm_eval!{ ctx, (throw 'any) }
             }, ()).is_err());
 
-            assert!(Guile::call_with_catch_all(|_| {
-                scm_eval!{ "test" }
-            }, ()).unwrap().equal_p(&Scm::<StringSpec>::from("test")).is_true());
+            assert!(Guile::call_with_catch_all(ctx, |_| {
+                scm_eval!{ ctx, "test" }
+            }, ()).unwrap().equal_p(&Scm::<StringSpec>::from(ctx, "test")).is_true());
 
             #[allow(dead_code)]
             struct TestStruct {
@@ -128,19 +176,22 @@ mod tests {
 
 
             lazy_static! {
-                static ref FTYPE: Scm<ForeignTypeSpec> = {
-                    Guile::call_with_guile(|_| {
-                        Scm::new_type(&"Test".into(), &vec![Scm::<StringSpec>::from("val1")].into(), type_list![TestStruct])
+                static ref FTYPE: Rooted<ForeignTypeSpec> = {
+                    Guile::call_with_guile(|ctx, _| {
+                        Scm::new_type(&Scm::from(ctx, "Test"),
+                                      &Scm::from(ctx, vec![Scm::<StringSpec>::from(ctx, "val1")]),
+                                      type_list![TestStruct])
+                            .into_rooted()
                     }, ())
                 };
-                static ref FSLOTS: Box<TypeList> = type_list![TestStruct];
+                static ref FSLOTS: Box<dyn TypeList> = type_list![TestStruct];
             }
 
             struct TestType { }
             impl ForeignType for TestType {
                 type Struct = TestStruct;
-                fn get_type<'a>() -> &'a Scm<ForeignTypeSpec> { &FTYPE }
-                fn get_slot_types() -> Box<TypeList> {
+                fn get_type<'g>(ctx: GuileCtx<'g>) -> Scm<'g, ForeignTypeSpec> { FTYPE.get(ctx) }
+                fn get_slot_types() -> Box<dyn TypeList> {
                     // Box clone clones the boxes contents
                     FSLOTS.clone()
                 }
@@ -165,18 +216,994 @@ mod tests {
 
         }, ());
 
-        let _ = Guile::call_with_guile(|_| {
-            Guile::eval("(define h (make-hash-table 32))");
-            Guile::eval(r#"(hashq-set! h 'foo "bar")"#);
+        let _ = Guile::call_with_guile(|ctx, _| {
+            Guile::eval(ctx, "(define h (make-hash-table 32))");
+            Guile::eval(ctx, r#"(hashq-set! h 'foo "bar")"#);
+        }, ());
+
+    }
+
+    #[test]
+    pub fn rooted_test() {
+        let _ = Guile::call_with_guile(|ctx, _| {
+            let rooted: Vec<Rooted<ScmString>> = (0..1000)
+                .map(|i| Scm::<ScmString>::from(ctx, format!("rooted string {}", i)).into_rooted())
+                .collect();
+
+            unsafe { guile_rs_sys::scm_gc(); }
+
+            for (i, s) in rooted.iter().enumerate() {
+                assert_eq!(s.get(ctx).to_string(), format!("rooted string {}", i));
+            }
+
+            let copy = rooted[7].clone();
+            drop(rooted);
+            assert_eq!(copy.into_inner(ctx).to_string(), "rooted string 7");
+        }, ());
+
+        // a root outlives the guile mode it was made in
+        let kept = Guile::call_with_guile(|ctx, _| Scm::<ScmString>::from(ctx, "kept").into_rooted(), ());
+        Guile::call_with_guile(|ctx, kept| {
+            unsafe { guile_rs_sys::scm_gc(); }
+            assert_eq!(kept.get(ctx).to_string(), "kept");
+        }, &kept);
+    }
+
+    #[test]
+    pub fn root_scope_test() {
+        let _ = Guile::call_with_guile(|ctx, _| {
+            RootScope::scope(ctx, |scope| {
+                let elems: Vec<Scm<Int>> = (0..10000).map(|i| scope.root(Scm::from(ctx, i))).collect();
+                assert_eq!(scope.len(), 10000);
+
+                unsafe { guile_rs_sys::scm_gc(); }
+
+                let list: Scm<List> = Scm::from(ctx, elems);
+                let n: i32 = list.length().try_as().unwrap();
+                assert_eq!(n, 10000);
+            });
         }, ());
+    }
 
+    #[test]
+    pub fn send_scm_test() {
+        let sent: SendScm<ScmString> = thread::spawn(|| {
+            Guile::call_with_guile(|ctx, _| {
+                Scm::<ScmString>::from(ctx, "from a worker").into_send()
+            }, ())
+        }).join().unwrap();
+
+        Guile::call_with_guile(|ctx, sent: SendScm<ScmString>| {
+            assert_eq!(sent.get(ctx).to_string(), "from a worker");
+        }, sent);
     }
 
     #[test]
     pub fn scope_test() {
-        // FIXME: if we run Guile::call_with_guile here, we get segfault...
-        // (invalid memory reference)
-        // let _ = Guile::call_with_guile(|_| { }, ());
+        assert!(!Guile::in_guile_mode());
+        let _ = Guile::call_with_guile(|_, _| { assert!(Guile::in_guile_mode()); }, ());
+        let _ = Guile::call_with_guile(|_, _| { }, ());
+        assert!(!Guile::in_guile_mode());
+    }
+
+    #[test]
+    pub fn reentrant_test() {
+        let n: i32 = Guile::call_with_guile(|ctx, _| {
+            let outer = Scm::from(ctx, 20);
+
+            // nested entry reuses the thread's guile mode
+            let inner: i32 = Guile::call_with_guile(|ctx, _| {
+                Guile::eval(ctx, "(+ 1 2)").into_integer().unwrap().try_as().unwrap()
+            }, ());
+
+            let sum: Scm<Int> = Scm::from(ctx, inner);
+            (outer + sum).into_unspecified().into_integer().unwrap().try_as().unwrap()
+        }, ());
+        assert_eq!(n, 23);
+    }
+
+    #[test]
+    pub fn result_test() {
+        let words: Vec<String> = Guile::call_with_guile(|ctx, n| {
+            (0..n).map(|i| Guile::eval(ctx, &format!("\"word {}\"", i)).into_string().unwrap().to_string())
+                  .collect()
+        }, 3);
+        assert_eq!(words, vec!["word 0", "word 1", "word 2"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "panic from guile mode")]
+    pub fn panic_test() {
+        Guile::call_with_guile(|_, _| { panic!("panic from guile mode") }, ());
+    }
+
+    #[test]
+    pub fn catch_panic_test() {
+        let caught = ::std::panic::catch_unwind(|| {
+            Guile::call_with_guile(|ctx, _| {
+                let _ = Guile::call_with_catch_all(ctx, |_| -> Scm<Untyped> { panic!("panic in catch") }, ());
+            }, ())
+        });
+        assert!(caught.is_err());
+
+        // the thread is still usable after the panic
+        Guile::call_with_guile(|ctx, _| { assert!(Guile::eval(ctx, "#t").is_true()); }, ());
+
+        // a panic of a rust procedure comes back at the rust catch its throw reaches
+        let caught = ::std::panic::catch_unwind(|| {
+            Guile::call_with_guile(|ctx, _| {
+                Guile::define_fn(ctx, "rust-panicking", "", || -> i32 { panic!("panic in a procedure") });
+                let _ = Guile::eval_as::<i32>(ctx, "(rust-panicking)");
+            }, ())
+        });
+        assert!(caught.is_err());
+
+        // one handled by scheme is gone, it doesn't come back at a later call
+        Guile::call_with_guile(|ctx, _| {
+            let v = Guile::eval(ctx, "(catch 'rust-panic (lambda () (rust-panicking)) (lambda _ 'handled))");
+            assert!(v.is_symbol());
+        }, ());
+        Guile::call_with_guile(|ctx, _| { assert!(Guile::eval(ctx, "#t").is_true()); }, ());
+
+        // a panic of a handler comes back where its dynwind context ends, on its own thread
+        let caught = ::std::panic::catch_unwind(|| {
+            Guile::call_with_guile(|ctx, _| {
+                Guile::dynwind(ctx, |frame| frame.on_unwind(true, || panic!("panic in a handler")));
+                unreachable!();
+            }, ())
+        });
+        assert!(caught.is_err());
+        thread::spawn(|| Guile::call_with_guile(|ctx, _| { Guile::eval(ctx, "#t"); }, ())).join().unwrap();
+    }
+
+    #[test]
+    pub fn threads_test() {
+        let handles: Vec<_> = (0..8).map(|t| thread::spawn(move || {
+            for i in 0..50 {
+                let n: i32 = Guile::call_with_guile(|ctx, (t, i)| {
+                    let src = format!("(* {} {})", t, i);
+                    Guile::eval(ctx, &src).into_integer().unwrap().try_as().unwrap()
+                }, (t, i));
+                assert_eq!(n, t * i);
+            }
+            assert!(!Guile::in_guile_mode());
+        })).collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    #[test]
+    pub fn try_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let one  = Scm::<Int>::from(ctx, 1);
+            let zero = Scm::<Int>::from(ctx, 0);
+
+            let err = one.try_quotient(&zero).unwrap_err();
+            assert_eq!(err.key, "numerical-overflow");
+            assert!(one.try_sum(&zero).unwrap().is_num_eq(&one));
+
+            let big = Scm::<Int>::from(ctx, 1000);
+            assert_eq!(TryAs::<i8, ()>::try_as(&big), Err(()));
+        }, ());
+    }
+
+    #[test]
+    pub fn error_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let err = Guile::eval_as::<i32>(ctx, "(car 5)").unwrap_err();
+            assert!(err.is_wrong_type_arg());
+            assert_eq!(err.subr, Some("car".to_string()));
+            assert_eq!(err.irritants.last().map(|s| s.as_str()), Some("5"));
+            assert_eq!(err.to_string(), "In procedure car: Wrong type argument in position 1 (expecting pair): 5");
+
+            let err = Guile::eval_as::<i32>(ctx, "some-undefined-variable").unwrap_err();
+            assert!(err.is_unbound_variable());
+            assert_eq!(err.message, "Unbound variable: some-undefined-variable");
+
+            let err = Guile::call_with_catch_all(ctx, |_| {
+                scm_eval!{ ctx, (throw 'custom 1 "two") }
+            }, ()).unwrap_err();
+            assert_eq!(err.key, "custom");
+            assert_eq!(err.subr, None);
+            assert_eq!(err.irritants, vec!["1", "\"two\""]);
+
+            assert!(Guile::eval_as::<i32>(ctx, "\"not a number\"").unwrap_err().is_wrong_type_arg());
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(+ 1 2)"), Ok(3));
+            assert_eq!(Guile::eval_as::<String>(ctx, "(string-append \"a\" \"b\")"), Ok("ab".to_string()));
+            assert_eq!(Guile::eval_as::<bool>(ctx, "(> 2 1)"), Ok(true));
+        }, ());
+    }
+
+    #[test]
+    pub fn backtrace_test() {
+        Guile::call_with_guile(|ctx, _| {
+            Guile::eval(ctx, "(define (backtrace-test-inner x) (+ 1 (car x)))");
+
+            let err = Guile::try_eval(ctx, "(backtrace-test-inner 5)").unwrap_err();
+            let bt = err.backtrace.expect("no backtrace");
+            assert!(bt.frames.iter().any(|f| f.procedure == Some("backtrace-test-inner".to_string())));
+            assert!(bt.to_string().starts_with("scheme backtrace:"));
+
+            let err = Guile::call_with_catch_all(ctx, |_| -> Scm<Untyped> {
+                scm_eval!{ ctx, (backtrace-test-inner 5) }
+            }, ()).unwrap_err();
+            assert!(err.backtrace.is_some());
+
+            // capturing works whatever the current module binds
+            unsafe {
+                let user = guile_rs_sys::scm_current_module();
+                Guile::eval(ctx, "(define-module (guile-rs backtrace-test) #:pure)");
+                let err = Guile::try_eval(ctx, "(car 5)").unwrap_err();
+                guile_rs_sys::scm_set_current_module(user);
+                assert!(err.is_unbound_variable());
+                assert!(!err.backtrace.unwrap().frames.is_empty());
+            }
+        }, ());
+    }
+
+    #[test]
+    pub fn dynwind_test() {
+        struct Flag<'a>(&'a Cell<i32>);
+        impl<'a> Drop for Flag<'a> {
+            fn drop(&mut self) { self.0.set(self.0.get() + 1); }
+        }
+
+        Guile::call_with_guile(|ctx, _| {
+            let unwound = Cell::new(0);
+            let dropped = Cell::new(0);
+
+            assert!(Guile::call_with_catch_all(ctx, |_| {
+                Guile::dynwind(ctx, |frame| {
+                    frame.on_unwind(false, || unwound.set(unwound.get() + 1));
+                    let _flag = frame.guard(Flag(&dropped));
+                    scm_eval!{ ctx, (throw 'dynwind-test) }
+                })
+            }, ()).is_err());
+            assert_eq!((unwound.get(), dropped.get()), (1, 1));
+
+            // handlers without `explicit` only run on a non-local exit
+            let n = Guile::dynwind(ctx, |frame| {
+                frame.on_unwind(false, || unwound.set(unwound.get() + 1));
+                let flag = frame.guard(Flag(&dropped));
+                let (mut a, b) = (frame.own(vec![1]), frame.own(5));
+                a.push(*b);
+                a[1] + flag.0.get()
+            });
+            assert_eq!(n, 6);
+            assert_eq!((unwound.get(), dropped.get()), (1, 2));
+
+            // handlers of a rewindable context are owned by the GC, not dropped on exit
+            use std::sync::Arc;
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            let rewound = Arc::new(AtomicUsize::new(0));
+            let count = rewound.clone();
+            assert!(Guile::call_with_catch_all(ctx, |_| unsafe {
+                Guile::dynwind_rewindable(ctx, |frame| {
+                    frame.on_unwind(false, move || { count.fetch_add(1, Ordering::SeqCst); });
+                    scm_eval!{ ctx, (throw 'dynwind-test) }
+                })
+            }, ()).is_err());
+            assert_eq!(rewound.load(Ordering::SeqCst), 1);
+        }, ());
+    }
+
+    #[test]
+    pub fn define_fn_test() {
+        Guile::call_with_guile(|ctx, _| {
+            Guile::define_fn(ctx, "rust-add", "Add numbers.", |a: i32, b: Option<i32>, rest: Rest<i32>| {
+                a + b.unwrap_or(0) + rest.iter().sum::<i32>()
+            });
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(rust-add 1)"), Ok(1));
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(rust-add 1 2 3 4)"), Ok(10));
+            assert_eq!(Guile::eval_as::<String>(ctx, "(symbol->string (procedure-name rust-add))"), Ok("rust-add".to_string()));
+            assert_eq!(Guile::eval_as::<String>(ctx, "(procedure-documentation rust-add)"), Ok("Add numbers.".to_string()));
+
+            let err = Guile::eval_as::<i32>(ctx, "(rust-add 1 \"two\")").unwrap_err();
+            assert!(err.is_wrong_type_arg());
+            assert_eq!(err.subr, Some("rust-add".to_string()));
+            assert!(Guile::eval_as::<i32>(ctx, "(rust-add)").is_err());
+
+            fn double<'g>(ctx: GuileCtx<'g>, n: i32) -> Scm<'g, Int> { Scm::from(ctx, n * 2) }
+            Guile::define_fn(ctx, "rust-double", "", double);
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(rust-double 21)"), Ok(42));
+
+            Guile::define_fn(ctx, "rust-fail", "", |msg: String| -> Result<(), GuileError> { Err(msg.into()) });
+            let err = Guile::eval_as::<Scm<Untyped>>(ctx, "(rust-fail \"failed\")").unwrap_err();
+            assert_eq!((err.key.as_str(), err.message.as_str()), ("rust-error", "failed"));
+
+            // works whatever the current module binds
+            unsafe {
+                let user = guile_rs_sys::scm_current_module();
+                Guile::eval(ctx, "(define-module (guile-rs define-fn-test) #:pure)");
+                Guile::define_fn(ctx, "rust-inc", "", |a: i32, b: Option<i32>| a + b.unwrap_or(1));
+                let v = Guile::eval_as::<i32>(ctx, "(rust-inc 1)");
+                guile_rs_sys::scm_set_current_module(user);
+                assert_eq!(v, Ok(2));
+            }
+        }, ());
+    }
+
+    #[test]
+    pub fn closure_test() {
+        use std::sync::{Arc, Mutex};
+        use std::ffi::CString;
+
+        Guile::call_with_guile(|ctx, _| {
+            let log = Arc::new(Mutex::new(Vec::new()));
+
+            let l = log.clone();
+            let push = Scm::<Procedure>::from_closure(ctx, move |s: String| l.lock().unwrap().push(s));
+            let mut total = 0;
+            let add = Scm::<Procedure>::from_closure_mut(ctx, move |n: i32, more: Option<i32>| {
+                total += n + more.unwrap_or(0);
+                total
+            });
+
+            unsafe {
+                guile_rs_sys::scm_c_define(CString::new("closure-push").unwrap().as_ptr(), push.into_raw());
+                guile_rs_sys::scm_c_define(CString::new("closure-add").unwrap().as_ptr(), add.into_raw());
+            }
+
+            Guile::eval(ctx, "(closure-push \"a\") (closure-push \"b\")");
+            assert_eq!(*log.lock().unwrap(), vec!["a", "b"]);
+
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(closure-add 1)"), Ok(1));
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(closure-add 2 3)"), Ok(6));
+            assert!(Guile::eval_as::<bool>(ctx, "(equal? (procedure-minimum-arity closure-add) '(1 1 #f))").unwrap());
+        }, ());
+
+        // any thread may call a closure, calls from several at once take turns
+        let counter = Guile::call_with_guile(|ctx, _| {
+            let mut n = 0;
+            Scm::<Procedure>::from_closure_mut(ctx, move || { n += 1; n }).into_send()
+        }, ());
+        let callers: Vec<_> = (0..2).map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || Guile::call_with_guile(|ctx, counter| {
+                for _ in 0..1000 {
+                    counter.get(ctx).call0().unwrap();
+                }
+            }, &counter))
+        }).collect();
+        for caller in callers {
+            caller.join().unwrap();
+        }
+        Guile::call_with_guile(|ctx, counter| {
+            assert!(counter.get(ctx).call0().unwrap().into_integer().unwrap() == Scm::from(ctx, 2001));
+        }, &counter);
+
+        // the GC owns the closures, collecting a procedure drops its closure
+        let owned = Arc::new(());
+        Guile::call_with_guile(|ctx, owned| {
+            for _ in 0..100 {
+                let owned = owned.clone();
+                Scm::<Procedure>::from_closure(ctx, move || { let _ = &owned; });
+            }
+        }, &owned);
+        assert_eq!(Arc::strong_count(&owned), 101);
+
+        Guile::call_with_guile(|_, owned| {
+            // the GC is conservative, a few may stay reachable from stale stack slots
+            for _ in 0..10 {
+                unsafe {
+                    guile_rs_sys::scm_gc();
+                    guile_rs_sys::scm_run_finalizers();
+                }
+                if Arc::strong_count(owned) < 50 { break; }
+            }
+            assert!(Arc::strong_count(owned) < 50);
+        }, &owned);
+    }
+
+    #[test]
+    pub fn call_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let plus = Guile::eval(ctx, "+").into_procedure().unwrap();
+            let three = plus.call2(&Scm::<Int>::from(ctx, 1), &Scm::<Int>::from(ctx, 2)).unwrap();
+            assert!(three.into_integer().unwrap() == Scm::from(ctx, 3));
+            assert!(plus.call((1, 2, 3)).unwrap().into_integer().unwrap() == Scm::from(ctx, 6));
+            assert!(plus.call0().unwrap().into_integer().unwrap().is_zero());
+
+            let args: Scm<List> = Scm::from(ctx, vec![Scm::<Int>::from(ctx, 4), Scm::from(ctx, 5)]);
+            assert!(plus.apply(&args).unwrap().into_integer().unwrap() == Scm::from(ctx, 9));
+
+            let err = plus.call((1, "x")).unwrap_err();
+            assert!(err.is_wrong_type_arg());
+            assert!(!Guile::eval(ctx, "1").is_procedure());
+
+            let kw = Guile::eval(ctx, "(lambda* (a #:key (indent 0) (char #\\space)) (string-append (make-string indent char) a))")
+                .into_procedure().unwrap();
+            let s = kw.builder().arg("x").keyword("indent", 2).call().unwrap();
+            assert_eq!(s.into_string().unwrap().to_string(), "  x");
+        }, ());
+    }
+
+    #[test]
+    pub fn module_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let module = Scm::<Module>::define(ctx, "guile-rs test", |module| {
+                module.define_value("answer", &Scm::<Int>::from(ctx, 42));
+                module.define_value("hidden", &Scm::<Int>::from(ctx, 0));
+                module.export(&["answer"]);
+            }).unwrap();
+
+            assert!(module.lookup("answer").unwrap().into_integer().unwrap() == Scm::from(ctx, 42));
+            assert!(module.lookup("nothing").unwrap_err().is_unbound_variable());
+
+            let same = Scm::<Module>::resolve(ctx, "guile-rs test").unwrap();
+            assert!(same.lookup("hidden").is_ok());
+
+            Scm::<Module>::use_module(ctx, "guile-rs test").unwrap();
+            assert_eq!(Guile::eval_as::<i32>(ctx, "answer").unwrap(), 42);
+            assert!(Guile::eval_as::<i32>(ctx, "hidden").unwrap_err().is_unbound_variable());
+
+            // exporting from another module leaves the current one alone
+            let user = Scm::<Module>::current(ctx);
+            module.export(&["hidden"]);
+            assert!(Scm::<Module>::current(ctx).eq_p(&user).is_true());
+            assert_eq!(Guile::eval_as::<i32>(ctx, "hidden").unwrap(), 0);
+        }, ());
+    }
+
+    #[test]
+    pub fn variable_test() {
+        Guile::call_with_guile(|ctx, _| {
+            assert!(Guile::lookup(ctx, "guile-rs-tab-width").is_none());
+            let var = Guile::define(ctx, "guile-rs-tab-width", &Scm::<Int>::from(ctx, 4));
+            assert!(Guile::lookup(ctx, "guile-rs-tab-width").unwrap().into_integer().unwrap() == Scm::from(ctx, 4));
+
+            // the handle follows changes from both sides
+            Guile::eval(ctx, "(set! guile-rs-tab-width 8)");
+            assert!(var.get().unwrap().into_integer().unwrap() == Scm::from(ctx, 8));
+            var.set(&Scm::<Int>::from(ctx, 2));
+            assert_eq!(Guile::eval_as::<i32>(ctx, "guile-rs-tab-width").unwrap(), 2);
+
+            let cached = Scm::<Variable>::lookup(ctx, "guile-rs-tab-width").unwrap();
+            assert!(cached.is_eq(&var));
+            assert!(Guile::eval(ctx, "(module-variable (current-module) 'guile-rs-tab-width)").is_variable());
+        }, ());
+    }
+
+    #[test]
+    pub fn environment_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let a = Environment::fresh_user_module(ctx).unwrap();
+            let b = Environment::fresh_user_module(ctx).unwrap();
+
+            a.eval("(define guile-rs-env 1)").unwrap();
+            assert_eq!(a.eval_as::<i32>("(+ guile-rs-env 1)").unwrap(), 2);
+            assert!(b.eval("guile-rs-env").unwrap_err().is_unbound_variable());
+            assert!(Guile::lookup(ctx, "guile-rs-env").is_none());
+            // user bindings are still there
+            assert_eq!(b.eval_as::<i32>("(length (iota 3))").unwrap(), 3);
+
+            b.define("guile-rs-env", &Scm::<Int>::from(ctx, 5));
+            assert_eq!(Guile::eval_in(b.module(), "guile-rs-env").into_integer().unwrap(), Scm::from(ctx, 5));
+
+            let expr = Guile::eval(ctx, "'(* guile-rs-env 2)");
+            assert!(a.eval_scm(&expr).unwrap().into_integer().unwrap() == Scm::from(ctx, 2));
+            assert!(b.eval_scm(&expr).unwrap().into_integer().unwrap() == Scm::from(ctx, 10));
+
+            // environments kept on the heap survive collections
+            let envs: Vec<Box<Environment>> = (0..20).map(|i| {
+                let env = Environment::fresh_user_module(ctx).unwrap();
+                env.eval(&format!("(define guile-rs-env {})", i)).unwrap();
+                Box::new(env)
+            }).collect();
+            unsafe { guile_rs_sys::scm_gc(); }
+            for (i, env) in envs.iter().enumerate() {
+                assert_eq!(env.eval_as::<i32>("guile-rs-env").unwrap(), i as i32);
+            }
+        }, ());
+    }
+
+    #[test]
+    pub fn load_test() {
+        use std::fs;
+        use std::io::Write;
+
+        let dir = ::std::env::temp_dir().join("guile-rs-load-test");
+        fs::create_dir_all(dir.join("guile-rs")).unwrap();
+        fs::File::create(dir.join("good.scm")).unwrap().write_all(b"(define guile-rs-loaded 3)\n(* 2 guile-rs-loaded)\n").unwrap();
+        fs::File::create(dir.join("bad.scm")).unwrap().write_all(b"(define x 1)\n(car x)\n").unwrap();
+        fs::File::create(dir.join("nested.scm")).unwrap().write_all(b"(define y 2)\n(load \"bad.scm\")\n").unwrap();
+        fs::File::create(dir.join("unclosed.scm")).unwrap().write_all(b"(define x\n  (list 1 2)\n").unwrap();
+        fs::File::create(dir.join("guile-rs/plugin.scm")).unwrap()
+            .write_all(b"(define-module (guile-rs plugin) #:export (plugin-name))\n(define plugin-name \"plugin\")\n").unwrap();
+
+        Guile::call_with_guile(|ctx, dir| {
+            let v = Guile::primitive_load(ctx, dir.join("good.scm")).unwrap();
+            assert!(v.into_integer().unwrap() == Scm::from(ctx, 6));
+            assert_eq!(Guile::eval_as::<i32>(ctx, "guile-rs-loaded").unwrap(), 3);
+
+            let err = Guile::primitive_load(ctx, dir.join("bad.scm")).unwrap_err();
+            assert!(err.is_wrong_type_arg());
+            assert_eq!(err.location.unwrap().file, dir.join("bad.scm").to_string_lossy());
+
+            // through load-in-vicinity, an error in a nested load is located in the inner file
+            let v = Guile::load(ctx, dir.join("good.scm")).unwrap();
+            assert!(v.into_integer().unwrap() == Scm::from(ctx, 6));
+            let err = Guile::load(ctx, dir.join("nested.scm")).unwrap_err();
+            assert!(err.is_wrong_type_arg());
+            let location = err.location.unwrap();
+            assert!(location.file.ends_with("bad.scm"), "{}", location);
+            assert_eq!(location.line, Some(2));
+            let err = Guile::primitive_load(ctx, dir.join("nested.scm")).unwrap_err();
+            assert!(err.location.unwrap().file.ends_with("bad.scm"));
+
+            let err = Guile::primitive_load(ctx, dir.join("unclosed.scm")).unwrap_err();
+            assert_eq!(err.key, "read-error");
+            assert!(err.location.unwrap().line.is_some());
+
+            let err = Guile::primitive_load(ctx, dir.join("missing.scm")).unwrap_err();
+            assert!(err.location.is_some());
+
+            Guile::prepend_load_path(ctx, &dir);
+            assert_eq!(Guile::load_path(ctx)[0], dir.to_string_lossy());
+            let plugin = Guile::load_module(ctx, "(guile-rs plugin)").unwrap();
+            assert_eq!(plugin.lookup("plugin-name").unwrap().into_string().unwrap().to_string(), "plugin");
+            assert!(Guile::load_module(ctx, "(guile-rs missing)").is_err());
+        }, dir);
+    }
+
+    #[test]
+    pub fn runtime_test() {
+        // other tests may already have started guile, make sure of it
+        let runtime = GuileRuntime::get();
+        assert!(GuileRuntime::builder().auto_compile(false).warnings(WarningOutput::Discard).init().is_err());
+
+        let version = runtime.version();
+        assert!(version >= Version::new(2, 0, 0));
+        assert_eq!(Guile::call_with_guile(|ctx, _| Guile::eval_as::<String>(ctx, "(version)").unwrap(), ()),
+                   version.to_string());
+
+        assert!(runtime.provides("threads"));
+        assert!(!runtime.provides("guile-rs-no-such-feature"));
+        assert!(runtime.require(Version::new(2, 0, 0), &["threads"]).is_ok());
+
+        let err = runtime.require(Version::new(99, 0, 0), &[]).unwrap_err();
+        assert!(err.message.contains("too old"));
+        let err = runtime.require(version, &["guile-rs-no-such-feature"]).unwrap_err();
+        assert!(err.message.contains("guile-rs-no-such-feature"));
+    }
+
+    #[test]
+    pub fn capture_test() {
+        Guile::call_with_guile(|ctx, _| {
+            let out = Guile::eval_capturing(ctx, "(display \"test display...\") (display \"oops\" (current-error-port)) 3");
+            assert_eq!(out.stdout, "test display...");
+            assert_eq!(out.stderr, "oops");
+            assert!(out.value.unwrap().into_integer().unwrap() == Scm::from(ctx, 3));
+
+            // output before a throw is kept
+            let out = Guile::eval_capturing(ctx, "(display \"before\") (car 1)");
+            assert_eq!(out.stdout, "before");
+            assert!(out.value.unwrap_err().is_wrong_type_arg());
+
+            let mut buf: Vec<u8> = Vec::new();
+            let v = Guile::with_output_to(ctx, &mut buf, |ctx| Guile::eval_as::<i32>(ctx, "(write 'x) 1")).unwrap();
+            assert_eq!(v.unwrap(), 1);
+            assert_eq!(buf, b"x");
+
+            let mut buf: Vec<u8> = Vec::new();
+            let r = Guile::try_call(|| {
+                let _ = Guile::with_output_to(ctx, &mut buf, |ctx| Guile::eval(ctx, "(display \"partial\") (throw 'oops)"));
+            });
+            assert!(r.is_err());
+            assert_eq!(buf, b"partial");
+        }, ());
+    }
+
+    #[test]
+    pub fn port_test() {
+        use std::io::{self, Cursor, Write};
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Shared(Arc<Mutex<Vec<u8>>>, Arc<AtomicBool>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        impl Drop for Shared {
+            fn drop(&mut self) { self.1.store(true, Ordering::SeqCst); }
+        }
+
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Err(io::Error::new(io::ErrorKind::Other, "broken pipe")) }
+        }
+
+        let (buf, dropped) = (Arc::new(Mutex::new(Vec::new())), Arc::new(AtomicBool::new(false)));
+        let writer = Shared(buf.clone(), dropped.clone());
+
+        Guile::call_with_guile(|ctx, writer| {
+            let input = Scm::<Port>::from_reader(ctx, Cursor::new(b"(1 2 3) foo".to_vec()));
+            Guile::define(ctx, "guile-rs-in", &input);
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(apply + (read guile-rs-in))").unwrap(), 6);
+            assert_eq!(Guile::eval_as::<String>(ctx, "(symbol->string (read guile-rs-in))").unwrap(), "foo");
+            assert!(Guile::eval(ctx, "(eof-object? (read guile-rs-in))").is_true());
+
+            let output = Scm::<Port>::from_writer(ctx, writer);
+            output.set_buffering(Buffering::None);
+            Guile::define(ctx, "guile-rs-out", &output);
+            Guile::eval(ctx, "(display \"hello\" guile-rs-out)");
+            assert!(Guile::eval(ctx, "(output-port? guile-rs-out)").is_true());
+            output.close().unwrap();
+
+            let mut file = Cursor::new(Vec::new());
+            file.write_all(b"abcdef").unwrap();
+            let stream = Scm::<Port>::from_stream(ctx, file);
+            Guile::define(ctx, "guile-rs-stream", &stream);
+            assert_eq!(Guile::eval_as::<String>(ctx, "(begin (seek guile-rs-stream 2 SEEK_SET) (string (read-char guile-rs-stream)))").unwrap(), "c");
+
+            Guile::define(ctx, "guile-rs-broken", &Scm::<Port>::from_reader(ctx, Broken));
+            let err = Guile::try_eval(ctx, "(read-char guile-rs-broken)").unwrap_err();
+            assert_eq!(err.key, "rust-io-error");
+            assert_eq!(err.message, "broken pipe");
+        }, writer);
+
+        assert_eq!(&*buf.lock().unwrap(), b"hello");
+        assert!(dropped.load(Ordering::SeqCst));
+
+        // ports are shared by threads, their writes take turns
+        struct Counter(Arc<Mutex<usize>>);
+        impl Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> { *self.0.lock().unwrap() += buf.len(); Ok(buf.len()) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        let written = Arc::new(Mutex::new(0));
+        let port = Guile::call_with_guile(|ctx, written| {
+            let port = Scm::<Port>::from_writer(ctx, Counter(written));
+            port.set_buffering(Buffering::None);
+            port.into_send()
+        }, written.clone());
+        let writers: Vec<_> = (0..2).map(|_| {
+            let port = port.clone();
+            thread::spawn(move || Guile::call_with_guile(|ctx, port| {
+                let (port, display) = (port.get(ctx), Guile::eval(ctx, "display").into_procedure().unwrap());
+                for _ in 0..1000 {
+                    display.call(("x", &port)).unwrap();
+                }
+            }, &port))
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(*written.lock().unwrap(), 2000);
+
+        // a stream a panic went through is broken for good
+        struct Panicking;
+        impl Write for Panicking {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> { panic!("guile-rs test panic") }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        Guile::call_with_guile(|ctx, _| {
+            let port = Scm::<Port>::from_writer(ctx, Panicking);
+            port.set_buffering(Buffering::None);
+            Guile::define(ctx, "guile-rs-panicking", &port);
+            assert!(::std::panic::catch_unwind(|| Guile::try_eval(ctx, "(display \"x\" guile-rs-panicking)")).is_err());
+            let err = Guile::try_eval(ctx, "(display \"x\" guile-rs-panicking)").unwrap_err();
+            assert_eq!(err.key, "rust-io-error");
+        }, ());
+    }
+
+    #[test]
+    pub fn repl_server_test() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+
+        // read from the REPL until `want` shows up
+        fn expect(conn: &mut UnixStream, want: &str) {
+            let mut seen = String::new();
+            let mut buf = [0; 256];
+            while !seen.contains(want) {
+                let n = conn.read(&mut buf).expect("no answer from the REPL");
+                assert!(n > 0, "REPL closed before {:?}, got {:?}", want, seen);
+                seen.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+        }
+
+        let path = ::std::env::temp_dir().join(format!("guile-rs-repl-{}", ::std::process::id()));
+        let server = Guile::call_with_guile(|ctx, path| {
+            let module = Scm::<Module>::define(ctx, "guile-rs repl", |module| {
+                module.define_value("repl-answer", &Scm::<Int>::from(ctx, 42));
+            }).unwrap();
+            ReplServer::spawn_in(ReplAddress::Unix(path), &module).unwrap()
+        }, path.clone());
+
+        let mut conn = UnixStream::connect(&path).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        expect(&mut conn, "> ");
+        conn.write_all(b"(display \"out\") repl-answer\n").unwrap();
+        expect(&mut conn, "out");
+        expect(&mut conn, "42");
+
+        // stopping ends the open REPLs, even one stuck in an endless loop
+        let mut busy = UnixStream::connect(&path).unwrap();
+        busy.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        expect(&mut busy, "> ");
+        busy.write_all(b"(display \"looping\") (let loop () (loop))\n").unwrap();
+        expect(&mut busy, "looping");
+
+        server.stop();
+        assert!(!path.exists());
+        let mut rest = Vec::new();
+        let _ = conn.read_to_end(&mut rest);
+        let _ = busy.read_to_end(&mut rest);
+    }
+
+    #[test]
+    pub fn rpc_server_test() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+
+        fn request(conn: &mut BufReader<UnixStream>, req: &str) -> String {
+            conn.get_mut().write_all(format!("{}\n", req).as_bytes()).unwrap();
+            let mut reply = String::new();
+            conn.read_line(&mut reply).unwrap();
+            reply.trim_end().to_string()
+        }
+
+        let path = ::std::env::temp_dir().join(format!("guile-rs-rpc-{}", ::std::process::id()));
+        let server = RpcServer::spawn(&path).unwrap();
+
+        let connect = || {
+            let conn = UnixStream::connect(&path).unwrap();
+            conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            BufReader::new(conn)
+        };
+        let mut conn = connect();
+
+        assert_eq!(request(&mut conn, r#"(1 eval "(display \"hi\") (+ 1 2)")"#),
+                   r#"(1 (value . "3") (stdout . "hi") (stderr . "") (error . #f))"#);
+        assert_eq!(request(&mut conn, r#"(2 eval "(car 1)")"#),
+                   r#"(2 (value . #f) (stdout . "") (stderr . "") (error (key . wrong-type-arg) (subr . "car") (message . "Wrong type argument in position 1 (expecting pair): 1")))"#);
+        assert!(request(&mut conn, r#"(3 complete "call-with-output-")"#).contains(r#""call-with-output-string""#));
+        assert!(request(&mut conn, r#"(4 describe "car")"#).starts_with(r#"(4 (name . "car") (type . procedure)"#));
+        assert!(request(&mut conn, r#"(5 describe "guile-rs-unbound")"#).contains("(key . unbound-variable)"));
+        assert!(request(&mut conn, "(6 frobnicate)").contains("(key . rpc-error)"));
+        assert!(request(&mut conn, "(7 eval").contains("(error"));
+
+        // completion doesn't use the bindings of the connection's module
+        request(&mut conn, r#"(8 eval "(define-module (guile-rs rpc-test)) (define (string-prefix? . args) #f)")"#);
+        assert!(request(&mut conn, r#"(9 complete "call-with-output-")"#).contains(r#""call-with-output-string""#));
+        assert!(request(&mut conn, r#"(10 describe "string-prefix?")"#).contains("(type . procedure)"));
+
+        // endless loops, interrupted one at a time from another connection
+        conn.get_mut().write_all(b"(11 eval \"(let loop () (loop))\")\n").unwrap();
+        let mut busy = connect();
+        busy.get_mut().write_all(b"(12 eval \"(let loop () (loop))\")\n").unwrap();
+        let mut other = connect();
+        assert!(request(&mut other, "(1 interrupt)").contains("(key . rpc-error)"));
+        // retried until the evaluation has started
+        let mut interrupt = |target: u32| {
+            let req = format!("(2 interrupt {})", target);
+            let interrupted = (0..1000).any(|_| {
+                let done = request(&mut other, &req) != "(2 (interrupted . 0))";
+                if !done { ::std::thread::sleep(Duration::from_millis(10)); }
+                done
+            });
+            assert!(interrupted, "request {} was not running", target);
+        };
+        interrupt(11);
+        let mut reply = String::new();
+        conn.read_line(&mut reply).unwrap();
+        assert!(reply.contains("(key . interrupted)"));
+
+        // still running after the other one was interrupted
+        interrupt(12);
+        let mut reply = String::new();
+        busy.read_line(&mut reply).unwrap();
+        assert!(reply.starts_with("(12 ") && reply.contains("(key . interrupted)"));
+
+        // stopping interrupts the evaluations still running
+        busy.get_mut().write_all(b"(13 eval \"(let loop () (loop))\")\n").unwrap();
+        ::std::thread::sleep(Duration::from_millis(50));
+
+        server.stop();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    pub fn interrupt_test() {
+        use std::time::Duration;
+
+        Guile::call_with_guile(|ctx, _| {
+            let err = Guile::eval_with_timeout(ctx, "(let loop () (loop))", Duration::from_millis(50)).unwrap_err();
+            assert!(err.is_interrupted());
+            let v = Guile::eval_with_timeout(ctx, "(+ 1 2)", Duration::from_secs(10)).unwrap();
+            assert!(v.into_integer().unwrap() == Scm::from(ctx, 3));
+
+            let handle = InterruptHandle::new(ctx);
+            assert!(!handle.interrupt());
+
+            let other = handle.clone();
+            let stopper = thread::spawn(move || {
+                while !other.interrupt() {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            });
+            let err = Guile::eval_interruptible(ctx, &handle, "(let loop () (loop))").unwrap_err();
+            assert!(err.is_interrupted());
+            stopper.join().unwrap();
+
+            // nothing is left pending for later evaluations
+            assert_eq!(Guile::eval_as::<i32>(ctx, "(+ 1 1)").unwrap(), 2);
+
+            // scheme can't pass its throws for interrupts
+            assert!(!Guile::try_eval(ctx, "(throw 'interrupted)").unwrap_err().is_interrupted());
+            let err = Guile::try_eval(ctx, "(scm-error 'interrupted #f \"Evaluation interrupted\" '() #f)").unwrap_err();
+            assert_eq!(err.key, "interrupted");
+            assert!(!err.is_interrupted());
+
+            // a nested interruptible leaves the outer one armed
+            let err = Guile::interruptible(ctx, &handle, || {
+                let inner = Guile::interruptible(ctx, &handle, || Guile::eval(ctx, "(+ 1 2)"));
+                assert!(inner.is_ok());
+                let other = handle.clone();
+                let stopper = thread::spawn(move || while !other.interrupt() { thread::sleep(Duration::from_millis(5)); });
+                let ret = Guile::try_eval(ctx, "(let loop () (loop))");
+                stopper.join().unwrap();
+                ret
+            }).unwrap().unwrap_err();
+            assert!(err.is_interrupted());
+
+            // neither does a current module rebinding `lambda`
+            let prev = Scm::<Module>::current(ctx);
+            Environment::fresh_user_module(ctx).unwrap().module().set_current();
+            Guile::eval(ctx, "(define lambda 1)");
+            let handle = InterruptHandle::new(ctx);
+            let other = handle.clone();
+            let stopper = thread::spawn(move || while !other.interrupt() { thread::sleep(Duration::from_millis(5)); });
+            assert!(Guile::eval_interruptible(ctx, &handle, "(let loop () (loop))").unwrap_err().is_interrupted());
+            stopper.join().unwrap();
+            prev.set_current();
+        }, ());
+    }
+
+    #[test]
+    pub fn sandbox_test() {
+        use std::time::Duration;
+
+        Guile::call_with_guile(|ctx, _| {
+            let sandbox = Sandbox::builder(ctx)
+                .define_fn("host-width", "Width of the host.", || 80)
+                .build().unwrap();
+            // the module and the procedure only live in the sandbox
+            for _ in 0..3 {
+                unsafe { guile_rs_sys::scm_gc(); }
+            }
+
+            // the current module rebinding what sandbox evaluations could use doesn't matter
+            let prev = Scm::<Module>::current(ctx);
+            Environment::fresh_user_module(ctx).unwrap().module().set_current();
+            Guile::eval(ctx, "(define lambda 1) (define eof-object? 2) (define append 3)");
+            let other = Sandbox::builder(ctx).build().unwrap();
+            assert!(other.eval("(+ 1 2)").unwrap().into_integer().unwrap() == Scm::from(ctx, 3));
+            prev.set_current();
+
+            let v = sandbox.eval("(define (sq x) (* x x)) (sq (host-width))").unwrap();
+            assert!(v.into_integer().unwrap() == Scm::from(ctx, 6400));
+            // definitions stay in the sandbox
+            assert!(sandbox.eval("(sq 2)").is_ok());
+            assert!(Guile::lookup(ctx, "sq").is_none());
+
+            assert_eq!(sandbox.eval("(open-input-file \"/etc/passwd\")").unwrap_err(),
+                       SandboxError::Forbidden("open-input-file".to_string()));
+            assert_eq!(sandbox.eval("(let loop () (loop))").unwrap_err(), SandboxError::TimeLimit);
+            match sandbox.eval("(car 1)").unwrap_err() {
+                SandboxError::Error(err) => assert!(err.is_wrong_type_arg()),
+                err => panic!("unexpected {:?}", err),
+            }
+
+            let small = Sandbox::builder(ctx)
+                .bindings(&[BindingSet::Core, BindingSet::List])
+                .time_limit(Duration::from_secs(10))
+                .allocation_limit(100_000)
+                .build().unwrap();
+            assert_eq!(small.eval("(let loop ((l '())) (loop (cons 1 l)))").unwrap_err(), SandboxError::AllocationLimit);
+            assert_eq!(small.eval("(string-length \"abc\")").unwrap_err(),
+                       SandboxError::Forbidden("string-length".to_string()));
+        }, ());
+    }
+
+    #[test]
+    pub fn plugin_test() {
+        use std::fs;
+        use std::io::Write;
+
+        use std::sync::Arc;
+
+        let dir = ::std::env::temp_dir().join(format!("guile-rs-plugin-test-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("dir")).unwrap();
+        fs::File::create(dir.join("a.scm")).unwrap()
+            .write_all(b"(use-modules (guile-rs hooks))\n(define name \"a\")\n(add-hook! test-hook (lambda () name))\n\
+                         (define procs (map (lambda (i) (make-host-proc)) (iota 100)))\n(add-hook! test-hook (car procs))\n").unwrap();
+        fs::File::create(dir.join("broken.scm")).unwrap().write_all(b"(define name \"broken\")\n(car name)\n").unwrap();
+        fs::File::create(dir.join("dir/init.scm")).unwrap().write_all(b"(define name \"dir\")\n").unwrap();
+        fs::File::create(dir.join("notes.txt")).unwrap().write_all(b"not a plugin").unwrap();
+
+        // owned by the rust closures plugin `a` makes
+        thread_local!(static OWNED: Arc<()> = Arc::new(()));
+        fn make_host_proc<'g>(ctx: GuileCtx<'g>) -> Scm<'g, Procedure> {
+            let owned = OWNED.with(|owned| owned.clone());
+            Scm::<Procedure>::from_closure(ctx, move || { let _ = &owned; })
+        }
+        let owned = || OWNED.with(|owned| Arc::strong_count(owned));
+
+        Guile::call_with_guile(|ctx, dir| {
+            let hook = Guile::eval(ctx, "(make-hook)");
+            let make_host_proc = Scm::<Procedure>::from_closure(ctx, make_host_proc);
+            Scm::<Module>::define(ctx, "guile-rs hooks", |module| {
+                module.define_value("test-hook", &hook);
+                module.define_value("make-host-proc", &make_host_proc);
+                module.export(&["test-hook", "make-host-proc"]);
+            }).unwrap();
+            let hooks = |ctx| Guile::eval_as::<i32>(ctx, "(length (hook->list (@ (guile-rs hooks) test-hook)))").unwrap();
+
+            let mut plugins = PluginManager::new();
+            plugins.add_dir(&dir);
+            plugins.add_dir(dir.join("missing"));
+            plugins.scan().unwrap();
+            assert_eq!(plugins.plugins().iter().map(|p| p.name()).collect::<Vec<_>>(), vec!["a", "broken", "dir"]);
+
+            // the broken plugin doesn't stop the others
+            plugins.load_all(ctx);
+            assert!(plugins.plugin("a").unwrap().is_loaded());
+            assert!(plugins.plugin("dir").unwrap().is_loaded());
+            let failed: Vec<_> = plugins.failed().collect();
+            assert_eq!(failed.len(), 1);
+            assert!(failed[0].error().unwrap().is_wrong_type_arg());
+            assert!(failed[0].module(ctx).is_none());
+
+            // each plugin has its own module
+            let env = Environment::from_module(plugins.plugin("a").unwrap().module(ctx).unwrap());
+            assert_eq!(env.eval_as::<String>("name"), Ok("a".to_string()));
+            let a = plugins.plugin("a").unwrap().module(ctx).unwrap();
+            assert!(Guile::lookup(ctx, "name").is_none());
+            assert_eq!(hooks(ctx), 2);
+            // the thread local holds one more
+            assert_eq!(owned(), 101);
+
+            plugins.unload(ctx, "a").unwrap();
+            assert_eq!(plugins.plugin("a").unwrap().state(), &PluginState::Unloaded);
+            assert_eq!(hooks(ctx), 0);
+            assert!(a.lookup("name").is_err());
+
+            // unloading lets the closures of the plugin be collected, the GC is conservative
+            // so a few may stay reachable from stale stack slots
+            for _ in 0..10 {
+                unsafe {
+                    guile_rs_sys::scm_gc();
+                    guile_rs_sys::scm_run_finalizers();
+                }
+                if owned() < 50 { break; }
+            }
+            assert!(owned() < 50);
+
+            fs::File::create(dir.join("a.scm")).unwrap()
+                .write_all(b"(use-modules (guile-rs hooks))\n(add-hook! test-hook (lambda () 1))\n(add-hook! test-hook (lambda () 2))\n").unwrap();
+            plugins.reload(ctx, "a").unwrap();
+            assert_eq!(hooks(ctx), 2);
+            plugins.reload(ctx, "a").unwrap();
+            assert_eq!(hooks(ctx), 2);
+
+            plugins.disable(ctx, "a").unwrap();
+            assert_eq!(hooks(ctx), 0);
+            plugins.load_all(ctx);
+            assert_eq!(plugins.plugin("a").unwrap().state(), &PluginState::Disabled);
+            plugins.enable("a").unwrap();
+            plugins.load_all(ctx);
+            assert!(plugins.plugin("a").unwrap().is_loaded());
+
+            assert!(plugins.reload(ctx, "missing").is_err());
+        }, dir);
     }
 }
 
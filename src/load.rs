@@ -0,0 +1,117 @@
+//! Loading scheme files and managing the load paths
+
+use std::env;
+use std::ffi::CString;
+use std::path::Path;
+
+use scm::{Scm, Untyped, Module, Variable};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+// a variable exported by `(guile)`
+pub(crate) fn guile_variable<'g>(_ctx: GuileCtx<'g>, name: &str) -> Scm<'g, Variable> {
+    let (module, name) = (CString::new("guile").unwrap(), CString::new(name).unwrap());
+    Scm::_from_raw(unsafe { scm_c_public_variable(module.as_ptr(), name.as_ptr()) })
+}
+
+fn path_str(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+// the strings of a scheme list, skipping anything else
+pub(crate) unsafe fn string_list(mut list: SCM) -> Vec<String> {
+    let mut v = Vec::new();
+    while gu_scm_is_pair(list) == 1 {
+        let item = gu_scm_car(list);
+        if gu_scm_is_string(item) == 1 {
+            v.push(Scm::<ScmString>::_from_raw(item).to_string());
+        }
+        list = gu_scm_cdr(list);
+    }
+    v
+}
+
+impl Guile {
+    /// Load the file at `path`, compiling it first if auto-compilation is enabled
+    ///
+    /// A relative `path` is taken from the current directory, like `load` does at the REPL.
+    /// Returns the value of the last expression of the file.
+    pub fn load<'g, P: AsRef<Path>>(ctx: GuileCtx<'g>, path: P) -> Result<Scm<'g, Untyped>, GuileError> {
+        let file = path_str(path.as_ref());
+        let dir  = env::current_dir().map(|d| path_str(&d)).unwrap_or_else(|_| ".".to_string());
+
+        let load = guile_variable(ctx, "load-in-vicinity").get().expect("load-in-vicinity is unbound");
+        let load = load.into_procedure().expect("load-in-vicinity is not a procedure");
+
+        load.call((dir, file.clone()))
+            .map_err(|e| e.in_file(&file))
+    }
+
+    /// Load the file at `path` with `scm_c_primitive_load`, never compiling it
+    pub fn primitive_load<'g, P: AsRef<Path>>(_ctx: GuileCtx<'g>, path: P) -> Result<Scm<'g, Untyped>, GuileError> {
+        let file = path_str(path.as_ref());
+        let cfile = CString::new(file.as_str()).unwrap();
+
+        Self::catch_traced(unsafe { gu_SCM_BOOL_T() }, || Scm::_from_raw(unsafe { scm_c_primitive_load(cfile.as_ptr()) }))
+            .map_err(|e| e.in_file(&file))
+    }
+
+    /// Load the module `name` (like `"(tim plugins foo)"`) from the load path, returning
+    /// its public interface
+    ///
+    /// Unlike `Scm::<Module>::resolve`, a module with no file to load from is an error.
+    pub fn load_module<'g>(ctx: GuileCtx<'g>, name: &str) -> Result<Scm<'g, Module>, GuileError> {
+        let parts: Vec<&str> = name.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+            .split_whitespace()
+            .collect();
+        // the file it is loaded from, relative to the load path
+        let file = format!("{}.scm", parts.join("/"));
+
+        let resolve = guile_variable(ctx, "resolve-interface").get().expect("resolve-interface is unbound");
+        let resolve = resolve.into_procedure().expect("resolve-interface is not a procedure");
+
+        let mut list = unsafe { gu_SCM_EOL() };
+        for part in parts.iter().rev() {
+            let sym = CString::new(*part).unwrap();
+            list = unsafe { gu_scm_cons(scm_from_utf8_symbol(sym.as_ptr()), list) };
+        }
+        let list: Scm<'g, Untyped> = Scm::_from_raw(list);
+
+        resolve.call((list,))
+            .map(|module| Scm::_from_raw(module.data))
+            .map_err(|e| e.in_file(&file))
+    }
+
+    /// The directories searched for scheme source, `%load-path`
+    pub fn load_path(ctx: GuileCtx) -> Vec<String> {
+        let path = guile_variable(ctx, "%load-path").get();
+        path.map(|p| unsafe { string_list(p.data) }).unwrap_or_default()
+    }
+
+    /// Search `dir` for scheme source before the other directories of `%load-path`
+    pub fn prepend_load_path<P: AsRef<Path>>(ctx: GuileCtx, dir: P) {
+        Self::prepend(guile_variable(ctx, "%load-path"), ctx, dir.as_ref());
+    }
+
+    /// The directories searched for compiled files, `%load-compiled-path`
+    pub fn load_compiled_path(ctx: GuileCtx) -> Vec<String> {
+        let path = guile_variable(ctx, "%load-compiled-path").get();
+        path.map(|p| unsafe { string_list(p.data) }).unwrap_or_default()
+    }
+
+    /// Search `dir` for compiled files before the other directories of `%load-compiled-path`
+    pub fn prepend_load_compiled_path<P: AsRef<Path>>(ctx: GuileCtx, dir: P) {
+        Self::prepend(guile_variable(ctx, "%load-compiled-path"), ctx, dir.as_ref());
+    }
+
+    fn prepend(var: Scm<Variable>, ctx: GuileCtx, dir: &Path) {
+        let dir = Scm::<ScmString>::from_str(ctx, &path_str(dir));
+        let rest = var.get().map(|p| p.data).unwrap_or_else(|| unsafe { gu_SCM_EOL() });
+        let path: Scm<Untyped> = Scm::_from_raw(unsafe { gu_scm_cons(dir.data, rest) });
+        var.set(&path);
+    }
+}
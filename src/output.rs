@@ -0,0 +1,79 @@
+//! Capturing what scheme code writes to its output ports
+
+use std::io::{self, Write};
+
+use scm::{Scm, Untyped};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+/// Result of `Guile::eval_capturing`
+#[derive(Debug)]
+pub struct EvalOutput<'g> {
+    /// The value of the evaluation, or the error it threw
+    pub value:  Result<Scm<'g, Untyped>, GuileError>,
+    /// Everything written to `current-output-port`
+    pub stdout: String,
+    /// Everything written to `current-error-port`
+    pub stderr: String,
+}
+
+// the text written to an output string port so far, only call in guile mode
+unsafe fn port_string(port: SCM) -> String {
+    Scm::<ScmString>::_from_raw(scm_get_output_string(port)).to_string()
+}
+
+impl Guile {
+    /// Evaluate `s` with its standard output and error ports going to strings
+    ///
+    /// The output is kept when the evaluation throws.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let out = Guile::eval_capturing(ctx, "(display \"hi\") (+ 1 2)");
+    /// assert_eq!(out.stdout, "hi");
+    /// ```
+    pub fn eval_capturing<'g>(ctx: GuileCtx<'g>, s: &str) -> EvalOutput<'g> {
+        let (out, err) = unsafe { (scm_open_output_string(), scm_open_output_string()) };
+
+        let value = Guile::dynwind(ctx, |_| {
+            unsafe {
+                scm_dynwind_current_output_port(out);
+                scm_dynwind_current_error_port(err);
+            }
+            Guile::try_eval(ctx, s)
+        });
+
+        unsafe {
+            EvalOutput { value, stdout: port_string(out), stderr: port_string(err) }
+        }
+    }
+
+    /// Run `body` with `current-output-port` going to `out`
+    ///
+    /// The output is written to `out` once `body` is left, also when it is left by a throw
+    /// (the write error is lost then).
+    pub fn with_output_to<'g, W, F, R>(ctx: GuileCtx<'g>, out: &mut W, body: F) -> io::Result<R>
+        where W: Write + ?Sized, F: FnOnce(GuileCtx<'g>) -> R {
+        let port = unsafe { scm_open_output_string() };
+
+        let ret = {
+            let out = &mut *out;
+            Guile::dynwind(ctx, move |frame| {
+                unsafe { scm_dynwind_current_output_port(port); }
+                // a throw skips the write after the body
+                frame.on_unwind(false, move || {
+                    let _ = out.write_all(unsafe { port_string(port) }.as_bytes());
+                });
+                body(ctx)
+            })
+        };
+
+        out.write_all(unsafe { port_string(port) }.as_bytes())?;
+        out.flush()?;
+        Ok(ret)
+    }
+}
@@ -0,0 +1,280 @@
+//! Loading scheme plugins, each in a module of its own
+//!
+//! A plugin is a `<name>.scm` file or a `<name>/init.scm` directory inside one of the
+//! plugin directories. Its file is evaluated in a fresh user module, so its definitions
+//! don't clash with those of other plugins. A plugin failing to load is recorded and
+//! skipped, the others still load.
+//!
+//! Hooks a plugin adds with `add-hook!` are tracked, unloading the plugin removes them
+//! along with its bindings, so what it allocated can be collected.
+//!
+//! # Example
+//! ```rust,ignore
+//! let mut plugins = PluginManager::new();
+//! plugins.add_dir(config_dir.join("plugins"));
+//! plugins.scan()?;
+//! plugins.load_all(ctx);
+//!
+//! for plugin in plugins.failed() {
+//!     println!("{}: {}", plugin.name(), plugin.error().unwrap());
+//! }
+//! plugins.reload(ctx, "spell-check")?;
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use scm::{Scm, Module, Procedure, SendScm};
+use interp::{Guile, GuileCtx, RootProc};
+use env::Environment;
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+// (prepare module), defines an `add-hook!` and `remove-hook!` in module recording the hooks
+// it adds, returns a thunk removing them and the bindings of module
+const PREPARE: &str = "
+(lambda (module)
+  (let ((added '()))
+    (module-define! module 'add-hook!
+      (lambda* (hook proc #:optional append?)
+        (set! added (cons (cons hook proc) added))
+        (add-hook! hook proc append?)))
+    (module-define! module 'remove-hook!
+      (lambda (hook proc)
+        (set! added (filter (lambda (e) (not (and (eq? (car e) hook) (eq? (cdr e) proc)))) added))
+        (remove-hook! hook proc)))
+    (lambda ()
+      (for-each (lambda (e) (remove-hook! (car e) (cdr e))) added)
+      (set! added '())
+      ;; closures still referencing module don't keep its values alive
+      (hash-clear! (module-obarray module)))))";
+
+static PREPARE_PROC: RootProc = RootProc::new(PREPARE);
+
+// makes a module current until dropped, a panic of the loaded code included
+struct CurrentModule<'g> {
+    prev: Scm<'g, Module>,
+}
+
+impl<'g> CurrentModule<'g> {
+    fn set(module: &Scm<'g, Module>) -> CurrentModule<'g> {
+        CurrentModule { prev: module.set_current() }
+    }
+}
+
+impl<'g> Drop for CurrentModule<'g> {
+    fn drop(&mut self) {
+        self.prev.set_current();
+    }
+}
+
+/// Where a plugin is at
+#[derive(Clone, Debug, PartialEq)]
+pub enum PluginState {
+    /// Found but not loaded yet, or unloaded
+    Unloaded,
+    Loaded,
+    /// Loading threw this error
+    Failed(GuileError),
+    /// Left out by `load_all` until enabled again
+    Disabled,
+}
+
+/// A plugin found by `PluginManager::scan`
+#[derive(Debug)]
+pub struct Plugin {
+    name:   String,
+    path:   PathBuf,
+    state:  PluginState,
+    module: Option<SendScm<Module>>,
+    // removes its hooks and bindings
+    unload: Option<SendScm<Procedure>>,
+}
+
+impl Plugin {
+    #[inline]
+    pub fn name(&self) -> &str { &self.name }
+
+    /// The file the plugin is loaded from
+    #[inline]
+    pub fn path(&self) -> &Path { &self.path }
+
+    #[inline]
+    pub fn state(&self) -> &PluginState { &self.state }
+
+    #[inline]
+    pub fn is_loaded(&self) -> bool { self.state == PluginState::Loaded }
+
+    /// The error of the last load, if it failed
+    #[inline]
+    pub fn error(&self) -> Option<&GuileError> {
+        match self.state {
+            PluginState::Failed(ref err) => Some(err),
+            _                            => None,
+        }
+    }
+
+    /// The module of a loaded plugin
+    pub fn module<'g>(&self, ctx: GuileCtx<'g>) -> Option<Scm<'g, Module>> {
+        self.module.as_ref().map(|m| m.get(ctx))
+    }
+
+    fn load(&mut self, ctx: GuileCtx) -> Result<(), GuileError> {
+        self.unload(ctx);
+
+        let env = Environment::fresh_user_module(ctx)?;
+        let unload = Guile::try_call(|| unsafe { scm_call_1(PREPARE_PROC.get(), env.module().data) })?;
+        self.module = Some(SendScm::new(Scm::_from_raw(env.module().data)));
+        self.unload = Some(SendScm::new(Scm::<Procedure>::_from_raw(unload)));
+
+        let ret = {
+            let _current = CurrentModule::set(env.module());
+            Guile::primitive_load(ctx, &self.path)
+        };
+
+        match ret {
+            Ok(_) => {
+                self.state = PluginState::Loaded;
+                Ok(())
+            },
+            Err(err) => {
+                // drop what it did before failing
+                self.unload(ctx);
+                self.state = PluginState::Failed(err.clone());
+                Err(err)
+            },
+        }
+    }
+
+    fn unload(&mut self, ctx: GuileCtx) {
+        if let Some(unload) = self.unload.take() {
+            let _ = unload.get(ctx).call0();
+        }
+        self.module = None;
+        if self.state == PluginState::Loaded {
+            self.state = PluginState::Unloaded;
+        }
+    }
+}
+
+/// Finds, loads and unloads plugins, see the [module documentation](index.html)
+#[derive(Debug, Default)]
+pub struct PluginManager {
+    dirs:    Vec<PathBuf>,
+    plugins: Vec<Plugin>,
+}
+
+impl PluginManager {
+    pub fn new() -> PluginManager {
+        PluginManager::default()
+    }
+
+    /// Look for plugins in `dir`, on the next `scan`
+    pub fn add_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.dirs.push(dir.into());
+    }
+
+    /// Find the plugins of the plugin directories
+    ///
+    /// Plugins found before keep their state. A name found in several directories is taken
+    /// from the first one. Missing directories are skipped.
+    pub fn scan(&mut self) -> io::Result<()> {
+        for dir in &self.dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            let mut found = Vec::new();
+            for entry in entries {
+                let path = entry?.path();
+                let plugin = if path.is_dir() {
+                    let init = path.join("init.scm");
+                    if !init.is_file() { continue; }
+                    (path.file_name().unwrap().to_string_lossy().into_owned(), init)
+                } else if path.extension().map_or(false, |e| e == "scm") {
+                    (path.file_stem().unwrap().to_string_lossy().into_owned(), path.clone())
+                } else {
+                    continue;
+                };
+                found.push(plugin);
+            }
+            // load in a stable order
+            found.sort();
+
+            for (name, path) in found {
+                if self.plugins.iter().all(|p| p.name != name) {
+                    self.plugins.push(Plugin { name, path, state: PluginState::Unloaded, module: None, unload: None });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn plugins(&self) -> &[Plugin] { &self.plugins }
+
+    pub fn plugin(&self, name: &str) -> Option<&Plugin> {
+        self.plugins.iter().find(|p| p.name == name)
+    }
+
+    /// The plugins whose last load failed
+    pub fn failed(&self) -> impl Iterator<Item = &Plugin> {
+        self.plugins.iter().filter(|p| p.error().is_some())
+    }
+
+    /// Load every plugin that is neither loaded nor disabled
+    ///
+    /// Errors are recorded in the plugins, see `failed`.
+    pub fn load_all(&mut self, ctx: GuileCtx) {
+        for plugin in &mut self.plugins {
+            match plugin.state {
+                PluginState::Loaded | PluginState::Disabled => {},
+                _ => { let _ = plugin.load(ctx); },
+            }
+        }
+    }
+
+    /// Load the plugin `name`, unloading it first if it is loaded
+    pub fn load(&mut self, ctx: GuileCtx, name: &str) -> Result<(), GuileError> {
+        self.find(name)?.load(ctx)
+    }
+
+    /// Load the plugin `name` again from its file
+    #[inline]
+    pub fn reload(&mut self, ctx: GuileCtx, name: &str) -> Result<(), GuileError> {
+        self.load(ctx, name)
+    }
+
+    /// Remove the hooks and bindings of the plugin `name`
+    pub fn unload(&mut self, ctx: GuileCtx, name: &str) -> Result<(), GuileError> {
+        self.find(name)?.unload(ctx);
+        Ok(())
+    }
+
+    /// Unload the plugin `name` and leave it out of `load_all`
+    pub fn disable(&mut self, ctx: GuileCtx, name: &str) -> Result<(), GuileError> {
+        let plugin = self.find(name)?;
+        plugin.unload(ctx);
+        plugin.state = PluginState::Disabled;
+        Ok(())
+    }
+
+    /// Let `load_all` load the plugin `name` again, without loading it
+    pub fn enable(&mut self, name: &str) -> Result<(), GuileError> {
+        let plugin = self.find(name)?;
+        if plugin.state == PluginState::Disabled {
+            plugin.state = PluginState::Unloaded;
+        }
+        Ok(())
+    }
+
+    fn find(&mut self, name: &str) -> Result<&mut Plugin, GuileError> {
+        self.plugins.iter_mut().find(|p| p.name == name)
+            .ok_or_else(|| GuileError::new("plugin-error", &format!("No plugin named `{}'", name)))
+    }
+}
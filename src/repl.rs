@@ -0,0 +1,279 @@
+//! A REPL server for debugging a running program
+//!
+//! Every connection gets guile's REPL on its own thread, with its output going to the
+//! connection. Anyone able to connect can run any code in the program: only local sockets
+//! and loopback addresses are accepted.
+//!
+//! # Example
+//! ```rust,ignore
+//! let server = ReplServer::spawn(ReplAddress::Unix("/run/user/1000/tim-repl".into()))?;
+//! // $ socat - UNIX-CONNECT:/run/user/1000/tim-repl
+//! server.stop();
+//! ```
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use scm::{Scm, Untyped, Module, Port, Buffering, SendScm};
+use interp::{Guile, GuileCtx, RootProc};
+use error::GuileError;
+use interrupt::InterruptHandle;
+
+use guile_rs_sys::*;
+
+
+/// Where a `ReplServer` listens
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplAddress {
+    /// A unix domain socket, created at this path and removed on stop
+    Unix(PathBuf),
+    /// A TCP port, on a loopback address only
+    Tcp(SocketAddr),
+}
+
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+enum Conn {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Listener {
+    fn bind(addr: &ReplAddress) -> io::Result<Listener> {
+        match *addr {
+            ReplAddress::Unix(ref path) => UnixListener::bind(path).map(Listener::Unix),
+            ReplAddress::Tcp(ref addr) => {
+                if !addr.ip().is_loopback() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "REPL servers only listen on loopback addresses"));
+                }
+                TcpListener::bind(addr).map(Listener::Tcp)
+            },
+        }
+    }
+
+    fn accept(&self) -> io::Result<Conn> {
+        match *self {
+            Listener::Unix(ref l) => l.accept().map(|(s, _)| Conn::Unix(s)),
+            Listener::Tcp(ref l)  => l.accept().map(|(s, _)| Conn::Tcp(s)),
+        }
+    }
+
+    // the bound address, the port of `Tcp` may have been picked by the system
+    fn address(&self, requested: &ReplAddress) -> io::Result<ReplAddress> {
+        match *self {
+            Listener::Unix(_)    => Ok(requested.clone()),
+            Listener::Tcp(ref l) => l.local_addr().map(ReplAddress::Tcp),
+        }
+    }
+}
+
+impl Conn {
+    fn connect(addr: &ReplAddress) -> io::Result<Conn> {
+        match *addr {
+            ReplAddress::Unix(ref path) => UnixStream::connect(path).map(Conn::Unix),
+            ReplAddress::Tcp(ref addr)  => TcpStream::connect(addr).map(Conn::Tcp),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Conn> {
+        match *self {
+            Conn::Unix(ref s) => s.try_clone().map(Conn::Unix),
+            Conn::Tcp(ref s)  => s.try_clone().map(Conn::Tcp),
+        }
+    }
+
+    fn shutdown(&self) {
+        let _ = match *self {
+            Conn::Unix(ref s) => s.shutdown(Shutdown::Both),
+            Conn::Tcp(ref s)  => s.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Conn::Unix(ref mut s) => s.read(buf),
+            Conn::Tcp(ref mut s)  => s.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Conn::Unix(ref mut s) => s.write(buf),
+            Conn::Tcp(ref mut s)  => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Conn::Unix(ref mut s) => s.flush(),
+            Conn::Tcp(ref mut s)  => s.flush(),
+        }
+    }
+}
+
+// runs the REPL of a client, with all output going to it
+const SERVE_CLIENT: &str = "
+(lambda (in out module)
+  (parameterize ((current-input-port   in)
+                 (current-output-port  out)
+                 (current-error-port   out)
+                 (current-warning-port out))
+    (save-module-excursion
+      (lambda ()
+        (if module (set-current-module module))
+        ((@ (system repl repl) start-repl)))))
+  (close-port in)
+  (close-port out))";
+
+static SERVE_CLIENT_PROC: RootProc = RootProc::new(SERVE_CLIENT);
+
+// the open connections, to be shut down and interrupted on stop
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+struct Client {
+    control:   Conn,
+    interrupt: Option<InterruptHandle>,
+    thread:    JoinHandle<()>,
+}
+
+/// A REPL server running in the background, stopped by `stop` or on drop
+pub struct ReplServer {
+    address:  ReplAddress,
+    stopping: Arc<AtomicBool>,
+    clients:  Clients,
+    accept:   Option<JoinHandle<()>>,
+}
+
+impl ReplServer {
+    /// Listen on `addr`, connections evaluate in `(guile-user)`
+    pub fn spawn(addr: ReplAddress) -> io::Result<ReplServer> {
+        Self::_spawn(addr, None)
+    }
+
+    /// Listen on `addr`, connections evaluate in `module`
+    pub fn spawn_in<'g>(addr: ReplAddress, module: &Scm<'g, Module>) -> io::Result<ReplServer> {
+        Self::_spawn(addr, Some(SendScm::new(Scm::_from_raw(module.data))))
+    }
+
+    fn _spawn(addr: ReplAddress, module: Option<SendScm<Module>>) -> io::Result<ReplServer> {
+        let listener = Listener::bind(&addr)?;
+        let address  = listener.address(&addr)?;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+
+        let accept = {
+            let (stopping, clients) = (stopping.clone(), clients.clone());
+            thread::Builder::new().name("guile-repl-server".to_string()).spawn(move || {
+                while let Ok(conn) = listener.accept() {
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let _ = Self::serve(conn, module.clone(), &clients);
+                }
+            })?
+        };
+
+        Ok(ReplServer { address, stopping, clients, accept: Some(accept) })
+    }
+
+    fn serve(conn: Conn, module: Option<SendScm<Module>>, clients: &Clients) -> io::Result<()> {
+        let (input, output, control) = (conn.try_clone()?, conn.try_clone()?, conn);
+        let (made, interrupt) = mpsc::channel();
+
+        let thread = thread::Builder::new().name("guile-repl-client".to_string()).spawn(move || {
+            // the client only learns about errors from the closed connection
+            let _ = Guile::call_with_guile(|ctx, (input, output, module)| {
+                let handle = InterruptHandle::new(ctx);
+                let _ = made.send(handle.clone());
+                Guile::interruptible(ctx, &handle, || Self::run_client(ctx, input, output, module))
+            }, (input, output, module));
+        })?;
+        // none if the thread ended before making it
+        let interrupt = interrupt.recv().ok();
+
+        let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+        // forget the connections that have ended
+        clients.retain(|c| !c.thread.is_finished());
+        clients.push(Client { control, interrupt, thread });
+        Ok(())
+    }
+
+    fn run_client<'g>(ctx: GuileCtx<'g>, input: Conn, output: Conn, module: Option<SendScm<Module>>)
+        -> Result<(), GuileError> {
+        let input  = Scm::<Port>::from_reader(ctx, input);
+        let output = Scm::<Port>::from_writer(ctx, output);
+        // prompts don't end in a newline
+        output.set_buffering(Buffering::None);
+
+        // `#f` keeps the thread's current module
+        let module = module.map(|m| m.get(ctx).data).unwrap_or_else(|| unsafe { gu_SCM_BOOL_F() });
+        let module: Scm<'g, Untyped> = Scm::_from_raw(module);
+
+        Guile::try_call(|| unsafe { scm_call_3(SERVE_CLIENT_PROC.get(), input.data, output.data, module.data) })
+            .map(|_| ())
+    }
+
+    /// The address the server listens on, with the actual port for a TCP port 0
+    #[inline]
+    pub fn address(&self) -> &ReplAddress { &self.address }
+
+    /// Stop accepting connections, close the open ones and wait for their REPLs to end
+    ///
+    /// A REPL busy evaluating is interrupted, as often as it takes for it to end.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let accept = match self.accept.take() {
+            Some(accept) => accept,
+            None         => return,
+        };
+
+        self.stopping.store(true, Ordering::SeqCst);
+        // wake up the blocking accept
+        let _ = Conn::connect(&self.address);
+        let _ = accept.join();
+
+        let clients: Vec<_> = self.clients.lock().unwrap_or_else(|e| e.into_inner()).drain(..).collect();
+        for client in &clients {
+            client.control.shutdown();
+        }
+        for client in clients {
+            // the REPL handles errors of its evaluations, and may be in another one by then
+            while !client.thread.is_finished() {
+                if let Some(ref interrupt) = client.interrupt {
+                    interrupt.interrupt();
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = client.thread.join();
+        }
+
+        if let ReplAddress::Unix(ref path) = self.address {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for ReplServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
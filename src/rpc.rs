@@ -0,0 +1,383 @@
+//! Remote evaluation over a unix socket, for tools driving the interpreter
+//!
+//! Requests and responses are S-expressions, one per line. A request is
+//! `(id method args ...)`, its response `(id (field . value) ...)` with the `id` sent back
+//! as is. Requests of a connection are handled in order, an `interrupt` has to be sent
+//! over another connection. It stops the evaluation of the request with the id `target`,
+//! which should be unique across connections.
+//!
+//! | request                   | response fields                                        |
+//! |---------------------------|--------------------------------------------------------|
+//! | `(id eval "src")`         | `value` (as written), `stdout`, `stderr`, `error`      |
+//! | `(id complete "prefix")`  | `completions`, the bound names starting with `prefix`  |
+//! | `(id describe "name")`    | `name`, `type`, `documentation` and `arity`, or `value`|
+//! | `(id interrupt target)`   | `interrupted`, the number of evaluations interrupted   |
+//!
+//! A failing request has an `error` field of `((key . k) (subr . s) (message . m))`. An
+//! interrupted evaluation fails with the `interrupted` key.
+//!
+//! # Example
+//! ```text
+//! > (1 eval "(display \"hi\") (+ 1 2)")
+//! < (1 (value . "3") (stdout . "hi") (stderr . "") (error . #f))
+//! > (2 describe "car")
+//! < (2 (name . "car") (type . procedure) (documentation . "...") (arity 1 0 #f))
+//! ```
+
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use scm::{Scm, Module, SendScm};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx, RootProc};
+use error::GuileError;
+use output::EvalOutput;
+use interrupt::InterruptHandle;
+
+use guile_rs_sys::*;
+
+
+// (complete module prefix), the names bound in module or its imports
+const COMPLETE: &str = "
+(lambda (module prefix)
+  (let ((names '()))
+    (define (add! sym var)
+      (let ((name (symbol->string sym)))
+        (if (and (string-prefix? prefix name) (not (member name names)))
+            (set! names (cons name names)))))
+    (module-for-each add! module)
+    (for-each (lambda (m) (module-for-each add! m)) (module-uses module))
+    (sort names string<?)))";
+
+// (describe module name), the response fields or #f if unbound
+const DESCRIBE: &str = "
+(lambda (module name)
+  (let ((var (module-variable module (string->symbol name))))
+    (cond
+      ((not (and var (variable-bound? var))) #f)
+      ((procedure? (variable-ref var))
+       (list (cons 'name name)
+             (cons 'type 'procedure)
+             (cons 'documentation (procedure-documentation (variable-ref var)))
+             (cons 'arity (procedure-minimum-arity (variable-ref var)))))
+      (else
+       (list (cons 'name name)
+             (cons 'type 'value)
+             (cons 'value (format #f \"~s\" (variable-ref var))))))))";
+
+static COMPLETE_PROC: RootProc = RootProc::new(COMPLETE);
+static DESCRIBE_PROC: RootProc = RootProc::new(DESCRIBE);
+
+// the interrupt handles of the connections
+type Running = Arc<Mutex<Vec<Evaluating>>>;
+
+struct Evaluating {
+    connection: u64,
+    // the written id of the request being evaluated
+    request:    Option<String>,
+    interrupt:  InterruptHandle,
+}
+type Clients = Arc<Mutex<Vec<(UnixStream, JoinHandle<()>)>>>;
+
+/// A remote evaluation server on a unix socket, stopped by `stop` or on drop
+///
+/// Anyone able to connect can run any code in the program, keep the socket private.
+pub struct RpcServer {
+    path:     PathBuf,
+    stopping: Arc<AtomicBool>,
+    clients:  Clients,
+    running:  Running,
+    accept:   Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Listen on a socket at `path`, evaluating in `(guile-user)`
+    pub fn spawn<P: Into<PathBuf>>(path: P) -> io::Result<RpcServer> {
+        Self::_spawn(path.into(), None)
+    }
+
+    /// Listen on a socket at `path`, evaluating in `module`
+    pub fn spawn_in<'g, P: Into<PathBuf>>(path: P, module: &Scm<'g, Module>) -> io::Result<RpcServer> {
+        Self::_spawn(path.into(), Some(SendScm::new(Scm::_from_raw(module.data))))
+    }
+
+    fn _spawn(path: PathBuf, module: Option<SendScm<Module>>) -> io::Result<RpcServer> {
+        let listener = UnixListener::bind(&path)?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        let running: Running = Arc::new(Mutex::new(Vec::new()));
+
+        let accept = {
+            let (stopping, clients, running) = (stopping.clone(), clients.clone(), running.clone());
+            thread::Builder::new().name("guile-rpc-server".to_string()).spawn(move || {
+                let mut next_id = 0;
+                while let Ok((stream, _)) = listener.accept() {
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let conn = Connection { id: next_id, module: module.clone(), running: running.clone() };
+                    next_id += 1;
+                    let _ = conn.spawn(stream, &clients);
+                }
+            })?
+        };
+
+        Ok(RpcServer { path, stopping, clients, running, accept: Some(accept) })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Stop accepting connections, close the open ones and wait for their requests to end
+    ///
+    /// Evaluations still running are interrupted, as often as it takes for them to end.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let accept = match self.accept.take() {
+            Some(accept) => accept,
+            None         => return,
+        };
+
+        self.stopping.store(true, Ordering::SeqCst);
+        // wake up the blocking accept
+        let _ = UnixStream::connect(&self.path);
+        let _ = accept.join();
+
+        let clients: Vec<_> = self.clients.lock().unwrap_or_else(|e| e.into_inner()).drain(..).collect();
+        for &(ref conn, _) in &clients {
+            let _ = conn.shutdown(Shutdown::Both);
+        }
+        for (_, thread) in clients {
+            // the evaluation may handle the interrupt, or go on with another request
+            while !thread.is_finished() {
+                for e in self.running.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+                    e.interrupt.interrupt();
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = thread.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// a client connection, handled on its own thread in guile mode
+struct Connection {
+    id:      u64,
+    module:  Option<SendScm<Module>>,
+    running: Running,
+}
+
+impl Connection {
+    fn spawn(self, stream: UnixStream, clients: &Clients) -> io::Result<()> {
+        let control = stream.try_clone()?;
+        let thread = thread::Builder::new().name("guile-rpc-client".to_string()).spawn(move || {
+            // a failing connection only ends itself
+            let _ = Guile::call_with_guile(|ctx, (conn, stream)| conn.serve(ctx, stream), (self, stream));
+        })?;
+
+        let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+        // forget the connections that have ended
+        clients.retain(|&(_, ref thread)| !thread.is_finished());
+        clients.push((control, thread));
+        Ok(())
+    }
+
+    fn serve(&self, ctx: GuileCtx, stream: UnixStream) -> io::Result<()> {
+        if let Some(ref module) = self.module {
+            module.get(ctx).set_current();
+        }
+
+        let interrupt = InterruptHandle::new(ctx);
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+            .push(Evaluating { connection: self.id, request: None, interrupt: interrupt.clone() });
+
+        let ret = self.serve_requests(ctx, &interrupt, stream);
+        self.running.lock().unwrap_or_else(|e| e.into_inner()).retain(|e| e.connection != self.id);
+        ret
+    }
+
+    fn serve_requests(&self, ctx: GuileCtx, interrupt: &InterruptHandle, stream: UnixStream) -> io::Result<()> {
+        let mut out = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = self.handle(ctx, interrupt, &line);
+            out.write_all(reply.as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    // the written response to a request line
+    fn handle(&self, ctx: GuileCtx, interrupt: &InterruptHandle, line: &str) -> String {
+        let line = Scm::<ScmString>::from_str(ctx, line);
+        let request = Guile::try_call(|| unsafe { scm_read(scm_open_input_string(line.data)) });
+
+        let (id, fields) = match request {
+            Ok(request) if unsafe { scm_ilength(request) } >= 2 => unsafe {
+                let (id, method, args) = (gu_scm_car(request), scm_cadr(request), scm_cddr(request));
+                (id, self.dispatch(ctx, interrupt, id, method, args))
+            },
+            Ok(request) if unsafe { gu_scm_is_pair(request) } == 1 => unsafe {
+                (gu_scm_car(request), Err(rpc_error("a request is (id method args ...)")))
+            },
+            Ok(_)    => (unsafe { gu_SCM_BOOL_F() }, Err(rpc_error("a request is (id method args ...)"))),
+            Err(err) => (unsafe { gu_SCM_BOOL_F() }, Err(err)),
+        };
+
+        let fields = fields.unwrap_or_else(|err| {
+            let mut fields = Fields::new();
+            fields.add("error", error_value(ctx, &err));
+            fields.finish()
+        });
+        unsafe { GuileError::write(gu_scm_cons(id, fields)) }
+    }
+
+    fn dispatch(&self, ctx: GuileCtx, interrupt: &InterruptHandle, id: SCM, method: SCM, args: SCM)
+        -> Result<SCM, GuileError> {
+        let method = if unsafe { gu_scm_is_symbol(method) } == 1 {
+            unsafe { Scm::<ScmString>::_from_raw(scm_symbol_to_string(method)).to_string() }
+        } else {
+            return Err(rpc_error("the method of a request is a symbol"));
+        };
+
+        let mut fields = Fields::new();
+        match method.as_str() {
+            "eval" => {
+                let src = string_arg(args)?;
+                self.set_request(Some(unsafe { GuileError::write(id) }));
+                let out = Guile::interruptible(ctx, interrupt, || Guile::eval_capturing(ctx, &src))
+                    .unwrap_or_else(|err| EvalOutput { value: Err(err), stdout: String::new(), stderr: String::new() });
+                self.set_request(None);
+                match out.value {
+                    Ok(ref value) => {
+                        fields.add("value", str_value(ctx, &unsafe { GuileError::write(value.data) }));
+                    },
+                    Err(_) => fields.add("value", unsafe { gu_SCM_BOOL_F() }),
+                }
+                fields.add("stdout", str_value(ctx, &out.stdout));
+                fields.add("stderr", str_value(ctx, &out.stderr));
+                match out.value {
+                    Ok(_)        => fields.add("error", unsafe { gu_SCM_BOOL_F() }),
+                    Err(ref err) => fields.add("error", error_value(ctx, err)),
+                }
+            },
+            "complete" => {
+                let prefix = str_value(ctx, &string_arg(args)?);
+                let names = Guile::try_call(|| unsafe {
+                    scm_call_2(COMPLETE_PROC.get(), Scm::<Module>::current(ctx).data, prefix)
+                })?;
+                fields.add("completions", names);
+            },
+            "describe" => {
+                let name = string_arg(args)?;
+                let name_value = str_value(ctx, &name);
+                let found = Guile::try_call(|| unsafe {
+                    scm_call_2(DESCRIBE_PROC.get(), Scm::<Module>::current(ctx).data, name_value)
+                })?;
+                if unsafe { gu_scm_is_false(found) } {
+                    return Err(GuileError::new("unbound-variable", &format!("Unbound variable: {}", name)));
+                }
+                return Ok(found);
+            },
+            "interrupt" => {
+                if unsafe { scm_ilength(args) } != 1 {
+                    return Err(rpc_error("interrupt takes the id of the request to interrupt"));
+                }
+                let count = self.interrupt(&unsafe { GuileError::write(gu_scm_car(args)) });
+                fields.add("interrupted", unsafe { scm_from_uint64(count as u64) });
+            },
+            _ => return Err(rpc_error(&format!("unknown method `{}'", method))),
+        }
+        Ok(fields.finish())
+    }
+
+    // record the request this connection evaluates, for `interrupt`
+    fn set_request(&self, request: Option<String>) {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = running.iter_mut().find(|e| e.connection == self.id) {
+            e.request = request;
+        }
+    }
+
+    // interrupt the evaluations of the requests with the written id `request`
+    fn interrupt(&self, request: &str) -> usize {
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        running.iter()
+            .filter(|e| e.request.as_ref().map_or(false, |r| r == request))
+            .filter(|e| e.interrupt.interrupt())
+            .count()
+    }
+}
+
+// the fields of a response, consed in reverse and kept on the stack for the GC
+struct Fields {
+    list: SCM,
+}
+
+impl Fields {
+    fn new() -> Fields {
+        Fields { list: unsafe { gu_SCM_EOL() } }
+    }
+
+    fn add(&mut self, name: &str, value: SCM) {
+        unsafe {
+            let name = scm_from_utf8_symbol(CString::new(name).unwrap().as_ptr());
+            self.list = gu_scm_cons(gu_scm_cons(name, value), self.list);
+        }
+    }
+
+    fn finish(self) -> SCM {
+        unsafe { scm_reverse(self.list) }
+    }
+}
+
+fn str_value(ctx: GuileCtx, s: &str) -> SCM {
+    Scm::<ScmString>::from_str(ctx, s).data
+}
+
+fn error_value(ctx: GuileCtx, err: &GuileError) -> SCM {
+    let mut fields = Fields::new();
+    fields.add("key", unsafe { scm_from_utf8_symbol(CString::new(err.key.as_str()).unwrap().as_ptr()) });
+    fields.add("subr", match err.subr {
+        Some(ref subr) => str_value(ctx, subr),
+        None           => unsafe { gu_SCM_BOOL_F() },
+    });
+    fields.add("message", str_value(ctx, &err.message));
+    fields.finish()
+}
+
+fn rpc_error(message: &str) -> GuileError {
+    GuileError::new("rpc-error", message)
+}
+
+// the only argument, a string
+fn string_arg(args: SCM) -> Result<String, GuileError> {
+    unsafe {
+        if scm_ilength(args) == 1 && gu_scm_is_string(gu_scm_car(args)) == 1 {
+            Ok(Scm::<ScmString>::_from_raw(gu_scm_car(args)).to_string())
+        } else {
+            Err(rpc_error("expected a string argument"))
+        }
+    }
+}
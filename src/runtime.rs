@@ -0,0 +1,319 @@
+//! Configuring guile before it starts
+//!
+//! Guile starts on the first `Guile::call_with_guile`. Building a `GuileRuntime` first lets
+//! the embedding program decide how it starts, later calls then run with these settings.
+//!
+//! # Example
+//! ```rust,ignore
+//! let runtime = GuileRuntime::builder()
+//!     .auto_compile(false)
+//!     .load_path("/usr/share/tim/scheme")
+//!     .honour_environment(false)
+//!     .warnings(WarningOutput::Discard)
+//!     .init()?;
+//!
+//! runtime.require(Version::new(2, 2, 0), &["threads"])?;
+//! ```
+
+use libc;
+use std::cell::Cell;
+use std::env;
+use std::ffi::CString;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use scm::{Scm, Untyped, Symbol};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+use load::{self, guile_variable};
+
+use guile_rs_sys::*;
+
+
+/// Where guile writes its warnings, like those of the compiler
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WarningOutput {
+    Stderr,
+    Discard,
+}
+
+// settings guile keeps per thread, applied on each thread's first entry
+#[derive(Clone, Debug)]
+struct ThreadConfig {
+    port_encoding: Option<String>,
+    warnings:      WarningOutput,
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static THREAD_CONFIG: Mutex<Option<ThreadConfig>> = Mutex::new(None);
+
+thread_local! {
+    static THREAD_CONFIGURED: Cell<bool> = Cell::new(false);
+}
+
+// called by `call_with_guile` when a thread enters guile mode
+pub(crate) fn enter_thread(ctx: GuileCtx) {
+    STARTED.store(true, Ordering::SeqCst);
+    if THREAD_CONFIGURED.with(|c| c.replace(true)) {
+        return;
+    }
+
+    let config = THREAD_CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(config) = config {
+        if let Some(encoding) = config.port_encoding {
+            let fluid = guile_variable(ctx, "%default-port-encoding").get();
+            if let Some(fluid) = fluid {
+                let encoding = Scm::<ScmString>::from_str(ctx, &encoding);
+                unsafe { scm_fluid_set_x(fluid.data, encoding.data); }
+            }
+        }
+        if config.warnings == WarningOutput::Discard {
+            let mode = CString::new("w").unwrap();
+            unsafe { scm_set_current_warning_port(scm_sys_make_void_port(mode.as_ptr())); }
+        }
+    }
+}
+
+
+// signals guile could take over during startup, leaving those of the collector alone
+const HOST_SIGNALS: &[libc::c_int] = &[
+    libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT,
+    libc::SIGPIPE, libc::SIGCHLD, libc::SIGWINCH,
+];
+
+
+/// Settings for starting guile, see `GuileRuntime::builder`
+#[derive(Clone, Debug)]
+pub struct RuntimeBuilder {
+    auto_compile:        Option<bool>,
+    load_path:           Vec<PathBuf>,
+    compiled_load_path:  Vec<PathBuf>,
+    honour_environment:  bool,
+    port_encoding:       Option<String>,
+    signal_handlers:     bool,
+    warnings:            WarningOutput,
+}
+
+impl RuntimeBuilder {
+    /// Compile loaded files to `.go` files first, by default as `GUILE_AUTO_COMPILE` says
+    pub fn auto_compile(mut self, on: bool) -> RuntimeBuilder {
+        self.auto_compile = Some(on);
+        self
+    }
+
+    /// Search `dir` for scheme source, before the default directories
+    ///
+    /// Directories added first are searched first.
+    pub fn load_path<P: Into<PathBuf>>(mut self, dir: P) -> RuntimeBuilder {
+        self.load_path.push(dir.into());
+        self
+    }
+
+    /// Search `dir` for compiled files, before the default directories
+    pub fn compiled_load_path<P: Into<PathBuf>>(mut self, dir: P) -> RuntimeBuilder {
+        self.compiled_load_path.push(dir.into());
+        self
+    }
+
+    /// Whether `GUILE_LOAD_PATH`, `GUILE_LOAD_COMPILED_PATH` and `GUILE_AUTO_COMPILE` are
+    /// read, on by default
+    ///
+    /// Guile reads them while starting, turning it off undoes what they did once it has
+    /// started: their directories are taken off the load paths and auto-compilation is back
+    /// to its default. The environment of the process is left alone.
+    pub fn honour_environment(mut self, on: bool) -> RuntimeBuilder {
+        self.honour_environment = on;
+        self
+    }
+
+    /// Encoding of ports opened without one, like `"UTF-8"`
+    pub fn port_encoding(mut self, encoding: &str) -> RuntimeBuilder {
+        self.port_encoding = Some(encoding.to_string());
+        self
+    }
+
+    /// Whether guile may install its own signal handlers while starting, on by default
+    ///
+    /// When off, the actions of `SIGINT`, `SIGTERM`, `SIGHUP`, `SIGQUIT`, `SIGPIPE`,
+    /// `SIGCHLD` and `SIGWINCH` are saved before guile initializes and restored right after,
+    /// undoing whatever it installed for them meanwhile. Handlers scheme code installs later
+    /// with `sigaction` are not affected.
+    pub fn signal_handlers(mut self, on: bool) -> RuntimeBuilder {
+        self.signal_handlers = on;
+        self
+    }
+
+    /// Where guile's warnings go, stderr by default
+    pub fn warnings(mut self, output: WarningOutput) -> RuntimeBuilder {
+        self.warnings = output;
+        self
+    }
+
+    /// Start guile with these settings
+    ///
+    /// Errors if guile was already started by this crate, the settings could not apply.
+    pub fn init(self) -> Result<GuileRuntime, GuileError> {
+        if STARTED.swap(true, Ordering::SeqCst) {
+            return Err(GuileError::new("runtime-error", "guile was already started, configure it before the first call"));
+        }
+
+        *THREAD_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(ThreadConfig {
+            port_encoding: self.port_encoding.clone(),
+            warnings:      self.warnings,
+        });
+
+        let saved = if self.signal_handlers { vec![] } else { save_signals() };
+
+        let RuntimeBuilder { auto_compile, load_path, compiled_load_path, honour_environment, .. } = self;
+        Guile::call_with_guile(|ctx, _| {
+            if !honour_environment {
+                forget_env_path(ctx, "%load-path", "GUILE_LOAD_PATH");
+                forget_env_path(ctx, "%load-compiled-path", "GUILE_LOAD_COMPILED_PATH");
+                if env::var_os("GUILE_AUTO_COMPILE").is_some() {
+                    set_bool(ctx, "%load-should-auto-compile", true);
+                    set_bool(ctx, "%fresh-auto-compile", false);
+                }
+            }
+            if let Some(on) = auto_compile {
+                set_bool(ctx, "%load-should-auto-compile", on);
+            }
+
+            // prepending, the last added ends up first
+            for dir in load_path.iter().rev() {
+                Guile::prepend_load_path(ctx, dir);
+            }
+            for dir in compiled_load_path.iter().rev() {
+                Guile::prepend_load_compiled_path(ctx, dir);
+            }
+        }, ());
+
+        restore_signals(saved);
+        Ok(GuileRuntime { _private: () })
+    }
+}
+
+fn save_signals() -> Vec<(libc::c_int, libc::sigaction)> {
+    HOST_SIGNALS.iter().filter_map(|&sig| unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        if libc::sigaction(sig, ::std::ptr::null(), &mut action) == 0 { Some((sig, action)) } else { None }
+    }).collect()
+}
+
+fn restore_signals(saved: Vec<(libc::c_int, libc::sigaction)>) {
+    for (sig, action) in saved {
+        unsafe { libc::sigaction(sig, &action, ::std::ptr::null_mut()); }
+    }
+}
+
+fn set_bool(ctx: GuileCtx, name: &str, on: bool) {
+    let var = guile_variable(ctx, name);
+    var.set(&Scm::<Untyped>::_from_raw(unsafe { if on { gu_SCM_BOOL_T() } else { gu_SCM_BOOL_F() } }));
+}
+
+// take the directories of the variable `env_var`, put there on startup, off the list `name`
+fn forget_env_path(ctx: GuileCtx, name: &str, env_var: &str) {
+    let from_env: Vec<String> = match env::var_os(env_var) {
+        Some(dirs) => env::split_paths(&dirs).map(|d| d.to_string_lossy().into_owned()).collect(),
+        None       => return,
+    };
+
+    let var = guile_variable(ctx, name);
+    let dirs = var.get().map(|p| unsafe { load::string_list(p.data) }).unwrap_or_default();
+    let mut list = unsafe { gu_SCM_EOL() };
+    for dir in dirs.iter().rev().filter(|d| !from_env.contains(d)) {
+        list = unsafe { gu_scm_cons(Scm::<ScmString>::from_str(ctx, dir).data, list) };
+    }
+    var.set(&Scm::<Untyped>::_from_raw(list));
+}
+
+
+/// A guile version, as `major.minor.micro`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub micro: u32,
+}
+
+impl Version {
+    #[inline]
+    pub fn new(major: u32, minor: u32, micro: u32) -> Version {
+        Version { major, minor, micro }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.micro)
+    }
+}
+
+
+/// The running guile, see `GuileRuntime::builder`
+#[derive(Clone, Copy, Debug)]
+pub struct GuileRuntime {
+    _private: (),
+}
+
+impl GuileRuntime {
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder {
+            auto_compile:       None,
+            load_path:          vec![],
+            compiled_load_path: vec![],
+            honour_environment: true,
+            port_encoding:      None,
+            signal_handlers:    true,
+            warnings:           WarningOutput::Stderr,
+        }
+    }
+
+    /// The running guile, starting it with the default settings if needed
+    pub fn get() -> GuileRuntime {
+        Guile::call_with_guile(|_, _| (), ());
+        GuileRuntime { _private: () }
+    }
+
+    /// The version of the linked libguile (`scm_version`)
+    pub fn version(&self) -> Version {
+        Guile::call_with_guile(|_, _| unsafe {
+            let part = |s: SCM| Scm::<ScmString>::_from_raw(s).to_string().parse().unwrap_or(0);
+            Version {
+                major: part(scm_major_version()),
+                minor: part(scm_minor_version()),
+                micro: part(scm_micro_version()),
+            }
+        }, ())
+    }
+
+    /// Whether `feature` is provided, as `(provided? 'feature)`
+    pub fn provides(&self, feature: &str) -> bool {
+        Guile::call_with_guile(|ctx, feature| {
+            let provided = guile_variable(ctx, "provided?").get().and_then(|p| p.into_procedure().ok());
+            let feature = Scm::<Symbol>::from_str(ctx, feature);
+            provided.and_then(|p| p.call((feature,)).ok()).map_or(false, |v| v.is_true())
+        }, feature)
+    }
+
+    /// Check for a version of at least `min` providing all of `features`
+    ///
+    /// The error says what is missing, to be shown to the user as is.
+    pub fn require(&self, min: Version, features: &[&str]) -> Result<(), GuileError> {
+        let version = self.version();
+        if version < min {
+            return Err(GuileError::new("runtime-error",
+                &format!("guile {} is too old, at least {} is required", version, min)));
+        }
+
+        let missing: Vec<&str> = features.iter().cloned().filter(|f| !self.provides(f)).collect();
+        if !missing.is_empty() {
+            return Err(GuileError::new("runtime-error",
+                &format!("guile {} was built without required features: {}", version, missing.join(", "))));
+        }
+        Ok(())
+    }
+}
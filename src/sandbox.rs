@@ -0,0 +1,254 @@
+//! Evaluating untrusted code with `(ice-9 sandbox)`
+//!
+//! Sandboxed code only sees the binding sets it is given, none of which can touch files,
+//! the network or foreign functions, and runs under time and allocation limits. Rust
+//! functions exported with `SandboxBuilder::define_fn` are its only way out.
+//!
+//! # Example
+//! ```rust,ignore
+//! let sandbox = Sandbox::builder(ctx)
+//!     .bindings(&[BindingSet::AllPure])
+//!     .time_limit(Duration::from_millis(50))
+//!     .define_fn("buffer-name", "Name of the current buffer.", buffer_name)
+//!     .build()?;
+//!
+//! match sandbox.eval(snippet) {
+//!     Err(SandboxError::TimeLimit) => println!("snippet took too long"),
+//!     ...
+//! }
+//! ```
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::time::Duration;
+
+use scm::{Scm, Untyped, Module, Procedure, Rooted};
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx, RootProc};
+use error::GuileError;
+use func::GuileFn;
+
+use guile_rs_sys::*;
+
+
+/// A set of bindings of `(ice-9 sandbox)`, like `list-bindings`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindingSet {
+    Core,
+    Number,
+    String,
+    Char,
+    Symbol,
+    List,
+    Pair,
+    Vector,
+    Hash,
+    Sort,
+    Iteration,
+    Error,
+    /// All the sets that can't mutate values, the default
+    AllPure,
+    /// All the sets, mutating ones included
+    AllPureAndImpure,
+    /// A set by its name in `(ice-9 sandbox)`, like `"mutating-list-bindings"`
+    Named(&'static str),
+}
+
+impl BindingSet {
+    fn name(&self) -> &'static str {
+        match *self {
+            BindingSet::Core             => "core-bindings",
+            BindingSet::Number           => "number-bindings",
+            BindingSet::String           => "string-bindings",
+            BindingSet::Char             => "char-bindings",
+            BindingSet::Symbol           => "symbol-bindings",
+            BindingSet::List             => "list-bindings",
+            BindingSet::Pair             => "pair-bindings",
+            BindingSet::Vector           => "vector-bindings",
+            BindingSet::Hash             => "hash-bindings",
+            BindingSet::Sort             => "sort-bindings",
+            BindingSet::Iteration        => "iteration-bindings",
+            BindingSet::Error            => "error-bindings",
+            BindingSet::AllPure          => "all-pure-bindings",
+            BindingSet::AllPureAndImpure => "all-pure-and-impure-bindings",
+            BindingSet::Named(name)      => name,
+        }
+    }
+}
+
+/// Why sandboxed code was stopped
+#[derive(Clone, Debug, PartialEq)]
+pub enum SandboxError {
+    /// It ran longer than the time limit
+    TimeLimit,
+    /// It allocated more than the allocation limit
+    AllocationLimit,
+    /// It used a name not bound in the sandbox, a forbidden or misspelled binding
+    Forbidden(String),
+    /// Any other error of the code, or of setting up the sandbox
+    Error(GuileError),
+}
+
+impl SandboxError {
+    fn from_error(err: GuileError) -> SandboxError {
+        match err.key.as_str() {
+            "limit-exceeded" if err.message.contains("Allocation") => SandboxError::AllocationLimit,
+            "limit-exceeded"   => SandboxError::TimeLimit,
+            "unbound-variable" => SandboxError::Forbidden(err.irritants.last().cloned().unwrap_or_default()),
+            _                  => SandboxError::Error(err),
+        }
+    }
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SandboxError::TimeLimit           => write!(f, "time limit exceeded"),
+            SandboxError::AllocationLimit     => write!(f, "allocation limit exceeded"),
+            SandboxError::Forbidden(ref name) => write!(f, "not allowed in the sandbox: {}", name),
+            SandboxError::Error(ref err)      => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SandboxError {}
+
+impl From<GuileError> for SandboxError {
+    fn from(err: GuileError) -> SandboxError { SandboxError::from_error(err) }
+}
+
+// (read-all src), the forms of src in a `begin`
+const READ_ALL: &str = "
+(lambda (src)
+  (let ((port (open-input-string src)))
+    (let loop ((forms '()))
+      (let ((form (read port)))
+        (if (eof-object? form)
+            (cons 'begin (if (null? forms) '((if #f #f)) (reverse forms)))
+            (loop (cons form forms)))))))";
+
+static READ_ALL_PROC: RootProc = RootProc::new(READ_ALL);
+
+// a procedure or binding set exported by `(ice-9 sandbox)`, loading it on first use
+fn sandbox_ref(name: &str) -> Result<SCM, GuileError> {
+    let (module, name) = (CString::new("ice-9 sandbox").unwrap(), CString::new(name).unwrap());
+    Guile::try_call(|| unsafe { scm_c_public_ref(module.as_ptr(), name.as_ptr()) })
+}
+
+/// Settings of a `Sandbox`, see `Sandbox::builder`
+pub struct SandboxBuilder<'g> {
+    ctx:              GuileCtx<'g>,
+    bindings:         Vec<BindingSet>,
+    time_limit:       Duration,
+    allocation_limit: u64,
+    // rooted until they are defined in the module, they own the rust closures
+    procedures:       Vec<(String, Rooted<Procedure>)>,
+}
+
+impl<'g> SandboxBuilder<'g> {
+    /// Add binding sets, `AllPure` if none are given
+    pub fn bindings(mut self, sets: &[BindingSet]) -> SandboxBuilder<'g> {
+        self.bindings.extend_from_slice(sets);
+        self
+    }
+
+    /// Stop evaluations running longer than `limit`, 100ms by default
+    pub fn time_limit(mut self, limit: Duration) -> SandboxBuilder<'g> {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Stop evaluations allocating more than `bytes`, 10MB by default
+    pub fn allocation_limit(mut self, bytes: u64) -> SandboxBuilder<'g> {
+        self.allocation_limit = bytes;
+        self
+    }
+
+    /// Make `fun` available as `name` in the sandbox, see `Guile::make_fn`
+    pub fn define_fn<F, A, R>(mut self, name: &str, doc: &str, fun: F) -> SandboxBuilder<'g>
+        where F: GuileFn<'g, A, R>, A: 'g, R: 'g {
+        let proc_ = Guile::make_fn(self.ctx, name, doc, fun);
+        self.procedures.push((name.to_string(), proc_.into_rooted()));
+        self
+    }
+
+    pub fn build(self) -> Result<Sandbox<'g>, GuileError> {
+        let sets = if self.bindings.is_empty() { vec![BindingSet::AllPure] } else { self.bindings };
+
+        // the bindings of all the sets, appended
+        let mut bindings = unsafe { gu_SCM_EOL() };
+        for set in &sets {
+            let mut set = sandbox_ref(set.name())?;
+            while unsafe { gu_scm_is_pair(set) } == 1 {
+                unsafe {
+                    bindings = gu_scm_cons(gu_scm_car(set), bindings);
+                    set = gu_scm_cdr(set);
+                }
+            }
+        }
+        let bindings = unsafe { scm_reverse(bindings) };
+
+        let make = sandbox_ref("make-sandbox-module")?;
+        let module: Scm<'g, Module> = Scm::_from_raw(Guile::try_call(|| unsafe { scm_call_1(make, bindings) })?);
+
+        for (name, proc_) in &self.procedures {
+            let name = CString::new(name.as_str()).unwrap();
+            unsafe { scm_module_define(module.data, scm_from_utf8_symbol(name.as_ptr()), proc_.get(self.ctx).data); }
+        }
+
+        Ok(Sandbox {
+            _root:            Rooted::new(Scm::_from_raw(module.data)),
+            module,
+            time_limit:       self.time_limit,
+            allocation_limit: self.allocation_limit,
+        })
+    }
+}
+
+/// A module to evaluate untrusted code in, see the [module documentation](index.html)
+///
+/// Definitions of one evaluation stay visible to the next.
+pub struct Sandbox<'g> {
+    module:           Scm<'g, Module>,
+    // keeps the module, and the procedures defined in it, alive as long as the sandbox
+    _root:            Rooted<Module>,
+    time_limit:       Duration,
+    allocation_limit: u64,
+}
+
+impl<'g> Sandbox<'g> {
+    pub fn builder(ctx: GuileCtx<'g>) -> SandboxBuilder<'g> {
+        SandboxBuilder {
+            ctx,
+            bindings:         vec![],
+            time_limit:       Duration::from_millis(100),
+            allocation_limit: 10_000_000,
+            procedures:       vec![],
+        }
+    }
+
+    #[inline]
+    pub fn module(&self) -> &Scm<'g, Module> { &self.module }
+
+    /// Evaluate the expressions of `src`, returning the value of the last one
+    pub fn eval(&self, src: &str) -> Result<Scm<'g, Untyped>, SandboxError> {
+        let ctx = self.module.ctx();
+        let src = Scm::<ScmString>::from_str(ctx, src);
+        let form = Guile::try_call(|| unsafe { scm_call_1(READ_ALL_PROC.get(), src.data) })?;
+        let eval = sandbox_ref("eval-in-sandbox")?;
+
+        let time_limit = self.time_limit.as_secs() as f64 + self.time_limit.subsec_nanos() as f64 / 1e9;
+        let keyword = |name: &str| unsafe { scm_from_utf8_keyword(CString::new(name).unwrap().as_ptr()) };
+        let args = unsafe { [
+            form,
+            keyword("module"),           self.module.data,
+            keyword("time-limit"),       scm_from_double(time_limit),
+            keyword("allocation-limit"), scm_from_uint64(self.allocation_limit),
+            keyword("sever-module?"),    gu_SCM_BOOL_F(),
+        ] };
+        let args = args.iter().rev().fold(unsafe { gu_SCM_EOL() }, |list, &arg| unsafe { gu_scm_cons(arg, list) });
+
+        Ok(Scm::_from_raw(Guile::try_call(|| unsafe { scm_apply_0(eval, args) })?))
+    }
+}
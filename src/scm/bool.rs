@@ -2,6 +2,7 @@ use std::ops::Not;
 
 use scm::Scm;
 use scm::TypeSpec;
+use interp::GuileCtx;
 
 use guile_rs_sys::*;
 
@@ -10,17 +11,17 @@ use guile_rs_sys::*;
 pub struct Bool;
 impl TypeSpec for Bool {}
 
-impl Scm<Bool> {
+impl<'g> Scm<'g, Bool> {
     /// Return a true litteral Scm object
     #[inline]
-    pub fn true_c() -> Scm<Bool> {
+    pub fn true_c(_ctx: GuileCtx<'g>) -> Scm<'g, Bool> {
         Scm::_from_raw(unsafe { gu_SCM_BOOL_T() })
         // Scm { data: unsafe { gu_SCM_BOOL_T() } , spec: PhantomData }
     }
 
     /// Return a false litteral Scm object
     #[inline]
-    pub fn false_c() -> Scm<Bool> {
+    pub fn false_c(_ctx: GuileCtx<'g>) -> Scm<'g, Bool> {
         Scm::_from_raw(unsafe { gu_SCM_BOOL_F() })
         // Scm { data: unsafe { gu_SCM_BOOL_F() }, spec: PhantomData }
     }
@@ -34,9 +35,9 @@ impl Scm<Bool> {
     }
 }
 
-impl Not for Scm<Bool> {
-    type Output = Scm<Bool>;
-    fn not(self) -> Scm<Bool> {
+impl<'g> Not for Scm<'g, Bool> {
+    type Output = Scm<'g, Bool>;
+    fn not(self) -> Scm<'g, Bool> {
         Scm::_from_raw(unsafe { scm_not(self.data) })
     }
 }
@@ -2,7 +2,7 @@
  //!
  //! # Example
  //! ```rust,ignore
- //! let s: Scm<Untyped> = Guile::eval("\"test string...\"");
+ //! let s: Scm<Untyped> = Guile::eval(ctx, "\"test string...\"");
  //! let s: Scm<String>      = s.into_string().unwrap();
  //! let s: String               = s.to_string();
  //! assert_eq!(s, "test string...");
@@ -13,15 +13,15 @@
  //! Operations on numerics produce unspecified numeric type ([NumericSpec](struct.NumericSpec.html))
  //!
  //! ```rust,ignore
- //! let r: Scm<NumericSpec> = Scm::from(9) + Scm::from(8)
- //! let r: Scm<NumericSpec> = r * Scm::from(90)
- //! let r: Scm<NumericSpec> = r / (Scm::from(123) - Scm::from(113));
+ //! let r: Scm<NumericSpec> = Scm::from(ctx, 9) + Scm::from(ctx, 8)
+ //! let r: Scm<NumericSpec> = r * Scm::from(ctx, 90)
+ //! let r: Scm<NumericSpec> = r / (Scm::from(ctx, 123) - Scm::from(ctx, 113));
  //!
  //! let rr = 9 + 8
  //! let rr = rr * 90
  //! let rr = rr / (123 - 113);
  //!
- //! assert!(Scm::from(rr) == r);
+ //! assert!(Scm::from(ctx, rr) == r);
  //! ```
  # [ allow (unused_macros) ] macro_rules! guile_defs {
 {
@@ -45,6 +45,11 @@ mod pair;
 mod list;
 mod hashtable;
 mod foreign;
+mod procedure;
+mod module;
+mod variable;
+mod port;
+mod rooted;
 pub use self :: untyped :: Untyped;
 pub use self :: bool :: Bool;
 pub use self :: string :: String;
@@ -58,7 +63,19 @@ HashTable , HashQTable , HashVTable , HashXTable
 pub use self :: foreign :: {
 Foreign , ForeignObject , ForeignSpec
 };
+pub use self :: procedure :: {
+Procedure , CallBuilder , IntoArg , IntoArgs
+};
+pub use self :: module :: Module;
+pub use self :: variable :: Variable;
+pub use self :: port :: {
+Port , Buffering
+};
+pub use self :: rooted :: {
+Rooted , RootScope , SendScm
+};
 use self :: guile_rs_sys :: *;
+use interp :: GuileCtx;
 use std :: marker :: PhantomData;
 use std :: ptr;
 use std :: mem :: transmute;
@@ -66,22 +83,32 @@ use std :: collections :: VecDeque;
 use libc;
 pub trait TypeSpec {
 } pub trait NumericSpec : TypeSpec {
-} # [ derive (Clone , Debug) ] pub struct Scm < TS : TypeSpec > {
-pub (crate) data : SCM , spec : PhantomData < TS >
-} unsafe impl < TS : TypeSpec > Send for Scm < TS > {
-} unsafe impl < TS : TypeSpec > Sync for Scm < TS > {
-} impl < TS : TypeSpec > Scm < TS > {
-# [ inline ] pub (crate) fn _from_raw (data : SCM) -> Scm < TS > {
+} /// A scheme value of type `TS`, valid while the `GuileCtx<'g>` it was created from
+ ///
+ /// `Scm` is neither `Send` nor `Sync`, use a [SendScm](struct.SendScm.html) to move
+ /// values between threads.
+ # [ derive (Clone , Debug) ] pub struct Scm < 'g , TS : TypeSpec > {
+pub (crate) data : SCM , spec : PhantomData < TS > , ctx : PhantomData < GuileCtx < 'g > > ,
+} impl < 'g , TS : TypeSpec > Scm < 'g , TS > {
+# [ inline ] pub (crate) fn _from_raw (data : SCM) -> Scm < 'g , TS > {
 Scm {
-data , spec : PhantomData
+data , spec : PhantomData , ctx : PhantomData
 }
-} # [ inline ] pub fn from_raw (data : SCM) -> Scm < Untyped > {
+} # [ inline ] pub fn from_raw (_ctx : GuileCtx < 'g > , data : SCM) -> Scm < 'g , Untyped > {
 Scm :: _from_raw (data)
+} /// Convert a rust value into a scheme value (see [IntoScm](trait.IntoScm.html))
+ # [ inline ] pub fn from < T : IntoScm < 'g , TS > > (ctx : GuileCtx < 'g > , value : T) -> Scm < 'g , TS > {
+value . into_scm (ctx)
 } # [ inline ] pub unsafe fn into_raw (self) -> SCM {
 self . data
-} fn into_type < S : TypeSpec > (self) -> Scm < S > {
+} /// Get the guile context this value lives in
+ # [ inline ] pub fn ctx (& self) -> GuileCtx < 'g > {
+unsafe {
+GuileCtx :: new ()
+}
+} fn into_type < S : TypeSpec > (self) -> Scm < 'g , S > {
 Scm :: _from_raw (self . data)
-} # [ inline ] pub fn into_unspecified (self) -> Scm < Untyped > {
+} # [ inline ] pub fn into_unspecified (self) -> Scm < 'g , Untyped > {
 Scm :: into_type (self)
 } # [ inline ] pub fn as_bits (& self) -> scm_t_bits {
 unsafe {
@@ -113,13 +140,19 @@ is_thing! (is_symbol => gu_scm_is_symbol);
 is_thing! (is_pair => gu_scm_is_pair);
 is_thing_manual! (is_list => scm_list_p);
 is_thing_manual! (is_hash_table => scm_hash_table_p);
+is_thing_manual! (is_procedure => scm_procedure_p);
+is_thing_manual! (is_variable => scm_variable_p);
+is_thing_manual! (is_port => scm_port_p);
 is_thing_p! (symbol_p => scm_symbol_p);
 is_thing_p! (pair_p => scm_pair_p);
 is_thing_p! (list_p => scm_list_p);
 is_thing_p! (hash_table_p => scm_hash_table_p);
+is_thing_p! (procedure_p => scm_procedure_p);
+is_thing_p! (variable_p => scm_variable_p);
+is_thing_p! (port_p => scm_port_p);
 /// check for identity (`scm_eq_p`)
  /// scheme operation: `eq?`
- # [ inline ] pub fn eq_p < OS : TypeSpec > (& self , other : & Scm < OS >) -> Scm < Bool > {
+ # [ inline ] pub fn eq_p < OS : TypeSpec > (& self , other : & Scm < OS >) -> Scm < 'g , Bool > {
 Scm :: _from_raw (unsafe {
 scm_eq_p (self . data , other . data)
 })
@@ -131,7 +164,7 @@ gu_scm_is_eq (self . data , other . data)
 }
 } is_thing_p! (equal_p (other : OS < TypeSpec >) => scm_equal_p) ;
 } /// A binary list of types known at compile time
- /// `Box<TypeList>` should always be built from the `type_list!()` macro!
+ /// `Box<dyn TypeList>` should always be built from the `type_list!()` macro!
  pub trait TypeList : Send + Sync {
 /// Drop the node's contents
  ///
@@ -140,16 +173,16 @@ gu_scm_is_eq (self . data , other . data)
 /// Get the length of the node
  /// 0 if node is a Nil
  fn len (& self) -> usize;
-fn cloned (& self) -> Box < TypeList > ;
-} impl Clone for Box < TypeList > {
+fn cloned (& self) -> Box < dyn TypeList > ;
+} impl Clone for Box < dyn TypeList > {
 fn clone (& self) -> Self {
 self . cloned ()
 }
 } /// A Type element from a `TypeList`
  pub trait TypeElem : Send + Sync {
 unsafe fn consume (& self , v : * mut libc :: c_void);
-fn cloned (& self) -> Box < TypeElem > ;
-} impl Clone for Box < TypeElem > {
+fn cloned (& self) -> Box < dyn TypeElem > ;
+} impl Clone for Box < dyn TypeElem > {
 fn clone (& self) -> Self {
 self . cloned ()
 }
@@ -163,7 +196,7 @@ impl < T : 'static + Send + Sync > TypeElem for TypeItem < T > {
  unsafe fn consume (& self , v : * mut libc :: c_void) {
 let v : Box < T > = Box :: from_raw (transmute (v));
 drop (v) ;
-} fn cloned (& self) -> Box < TypeElem > {
+} fn cloned (& self) -> Box < dyn TypeElem > {
 Box :: new (TypeItem :: < T > (PhantomData))
 }
 } # [ derive (Clone) ] /// Marks end of a TyepeList
@@ -173,11 +206,11 @@ unsafe fn consume_node (& self , v : VecDeque < * mut libc :: c_void >) {
 assert_eq! (v . len () , 0) ;
 } fn len (& self) -> usize {
 0
-} fn cloned (& self) -> Box < TypeList > {
+} fn cloned (& self) -> Box < dyn TypeList > {
 Box :: new (self . clone ())
 }
 } # [ derive (Clone) ] /// A node of the binary spine that makes the TypeList
- pub struct TypePair (pub Box < TypeElem > , pub Box < TypeList >);
+ pub struct TypePair (pub Box < dyn TypeElem > , pub Box < dyn TypeList >);
 impl TypeList for TypePair {
 unsafe fn consume_node (& self , mut v : VecDeque < * mut libc :: c_void >) {
 assert_eq! (v . len () , self . len ());
@@ -186,10 +219,10 @@ assert_eq! (v . len () , 1);
 self . 0 . consume (v [ 0 ]) ;
 } fn len (& self) -> usize {
 1 + self . 1 . len ()
-} fn cloned (& self) -> Box < TypeList > {
+} fn cloned (& self) -> Box < dyn TypeList > {
 Box :: new (self . clone ())
 }
-} /// Initialize a `Box<TypeList>`
+} /// Initialize a `Box<dyn TypeList>`
  # [ macro_export ] macro_rules! type_list {
 [ $ head : ty , $ ($ tail : ty) ,* ] => {
 {
@@ -207,15 +240,26 @@ Box :: new (Nil {
 })
 }
 } ;
-} impl < N : NumericSpec > From < Scm < N > > for Scm < self :: String > {
-fn from (numeric : Scm < N >) -> Scm < self :: String > {
-Self {
-data : unsafe {
-scm_number_to_string (numeric . data , ptr :: null_mut ())
-} , spec : PhantomData ,
-}
+} /// Conversion of a rust value into a scheme value of type `TS`
+ ///
+ /// Like `From` but requires a `GuileCtx`, use through `Scm::from(ctx, value)`.
+ pub trait IntoScm < 'g , TS : TypeSpec > {
+fn into_scm (self , ctx : GuileCtx < 'g >) -> Scm < 'g , TS > ;
+} impl < 'g , N : NumericSpec > IntoScm < 'g , self :: String > for Scm < 'g , N > {
+fn into_scm (self , _ctx : GuileCtx < 'g >) -> Scm < 'g , self :: String > {
+Scm :: _from_raw (unsafe {
+scm_number_to_string (self . data , ptr :: null_mut ())
+})
 }
 } pub trait TryAs < T , E > {
 /// attemp to get `&self` as type `T`
  fn try_as (& self) -> Result < T , E > ;
+} /// Conversion of an untyped scheme value into a rust value
+ ///
+ /// Used by `Guile::eval_as`, where the type of the result is only known at runtime.
+ pub trait FromScm < 'g > : Sized {
+/// `None` when `scm` has the wrong type or does not fit
+ fn from_scm (scm : Scm < 'g , Untyped >) -> Option < Self >;
+/// What a convertible value is, for error messages (like `exact integer`)
+ fn type_name () -> & 'static str ;
 }
\ No newline at end of file
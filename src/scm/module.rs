@@ -0,0 +1,111 @@
+use libc;
+use std::ffi::CString;
+
+use scm::*;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+use unwind::Callback;
+
+use guile_rs_sys::*;
+
+
+/// A scheme module, like `(tim buffer)`
+///
+/// Module names are given as space separated strings (`"tim buffer"`), as in the C API.
+///
+/// # Example
+/// ```rust,ignore
+/// Scm::<Module>::define(ctx, "tim buffer", |module| {
+///     Guile::define_fn(ctx, "buffer-name", "Name of the current buffer.", buffer_name);
+///     module.export(&["buffer-name"]);
+/// })?;
+///
+/// let user = Scm::<Module>::resolve(ctx, "guile-user")?;
+/// let width = user.lookup("tab-width")?;
+/// ```
+#[derive(Debug)]
+pub struct Module;
+impl TypeSpec for Module {}
+
+impl<'g> Scm<'g, Module> {
+    unsafe extern "C" fn init_module<F: FnOnce() -> R, R>(data: *mut libc::c_void) {
+        Callback::<F, R>::run(data);
+    }
+
+    #[inline]
+    fn init_fn<F: FnOnce() -> R, R>(_: &Callback<F, R>) -> unsafe extern "C" fn(*mut libc::c_void) {
+        Self::init_module::<F, R>
+    }
+
+    /// Define (or reopen) the module `name`, running `init` with it as the current module
+    pub fn define<F>(ctx: GuileCtx<'g>, name: &str, init: F) -> Result<Scm<'g, Module>, GuileError>
+        where F: FnOnce(&Scm<'g, Module>) {
+        let name = CString::new(name).unwrap();
+
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || {
+            let mut callback = Callback::new(move || {
+                init(&Scm::<Module>::current(ctx));
+            });
+            let module = unsafe {
+                scm_c_define_module(name.as_ptr(), Some(Self::init_fn(&callback)), callback.as_ptr())
+            };
+            callback.into_result();
+            Scm::_from_raw(module)
+        })
+    }
+
+    /// Find the module `name`, loading it from the load path if needed
+    pub fn resolve(_ctx: GuileCtx<'g>, name: &str) -> Result<Scm<'g, Module>, GuileError> {
+        let name = CString::new(name).unwrap();
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || {
+            Scm::_from_raw(unsafe { scm_c_resolve_module(name.as_ptr()) })
+        })
+    }
+
+    /// Import the public bindings of the module `name` into the current module
+    pub fn use_module(_ctx: GuileCtx<'g>, name: &str) -> Result<(), GuileError> {
+        let name = CString::new(name).unwrap();
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || unsafe { scm_c_use_module(name.as_ptr()); })
+    }
+
+    /// The module `eval` and definitions currently work in
+    #[inline]
+    pub fn current(_ctx: GuileCtx<'g>) -> Scm<'g, Module> {
+        Scm::_from_raw(unsafe { scm_current_module() })
+    }
+
+    /// Make this the current module, returning the previous one
+    #[inline]
+    pub fn set_current(&self) -> Scm<'g, Module> {
+        Scm::_from_raw(unsafe { scm_set_current_module(self.data) })
+    }
+
+    /// Bind `name` to `value` in this module, returning its variable
+    pub fn define_value<TS: TypeSpec>(&self, name: &str, value: &Scm<TS>) -> Scm<'g, Variable> {
+        let name = CString::new(name).unwrap();
+        Scm::_from_raw(unsafe { scm_c_module_define(self.data, name.as_ptr(), value.data) })
+    }
+
+    /// The value bound to `name` in this module, an `unbound-variable` error if none
+    pub fn lookup(&self, name: &str) -> Result<Scm<'g, Untyped>, GuileError> {
+        let name = CString::new(name).unwrap();
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || unsafe {
+            Scm::_from_raw(scm_variable_ref(scm_c_module_lookup(self.data, name.as_ptr())))
+        })
+    }
+
+    /// Add bindings of this module to its public interface
+    pub fn export(&self, names: &[&str]) {
+        unsafe {
+            let mut list = gu_SCM_EOL();
+            for name in names.iter().rev() {
+                list = gu_scm_cons(scm_from_utf8_symbol(CString::new(*name).unwrap().as_ptr()), list);
+            }
+
+            // `scm_c_export` only works on the current module
+            let export = scm_c_public_ref(CString::new("guile").unwrap().as_ptr(),
+                                          CString::new("module-export!").unwrap().as_ptr());
+            scm_call_2(export, self.data, list);
+        }
+    }
+}
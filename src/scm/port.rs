@@ -0,0 +1,223 @@
+use libc;
+use std::ffi::CString;
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::sync::Mutex;
+
+use scm::*;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+use unwind;
+
+use guile_rs_sys::*;
+
+
+/// A scheme port, see `Scm::<Port>::from_reader`
+///
+/// Ports made from rust streams own them, the stream is dropped when the port is closed
+/// or collected. Errors of the stream are thrown to scheme with the `rust-io-error` key.
+///
+/// # Example
+/// ```rust,ignore
+/// let log = Scm::<Port>::from_writer(ctx, File::create("tim.log")?);
+/// log.set_buffering(Buffering::Line);
+/// Guile::define(ctx, "log-port", &log);
+/// ```
+#[derive(Debug)]
+pub struct Port;
+impl TypeSpec for Port {}
+
+/// How a port buffers, see `Scm::<Port>::set_buffering`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Buffering {
+    /// Every read and write goes to the stream
+    None,
+    /// Output is written at each newline
+    Line,
+    /// Reads and writes go through a buffer of this many bytes
+    Block(usize),
+}
+
+impl<'g> Scm<'g, Port> {
+    /// An input port reading from `reader`
+    pub fn from_reader<R: Read + Send + 'static>(ctx: GuileCtx<'g>, reader: R) -> Scm<'g, Port> {
+        Self::make(ctx, Box::new(Reader(reader)), "r", false)
+    }
+
+    /// An output port writing to `writer`
+    ///
+    /// The writer is flushed each time the port's buffer is.
+    pub fn from_writer<W: Write + Send + 'static>(ctx: GuileCtx<'g>, writer: W) -> Scm<'g, Port> {
+        Self::make(ctx, Box::new(Writer(writer)), "w", false)
+    }
+
+    /// An input and output port on a seekable `stream`, like a file
+    pub fn from_stream<T: Read + Write + Seek + Send + 'static>(ctx: GuileCtx<'g>, stream: T) -> Scm<'g, Port> {
+        Self::make(ctx, Box::new(ReadWriteSeek(stream)), "r+", true)
+    }
+
+    fn make(_ctx: GuileCtx<'g>, stream: Box<dyn Stream>, mode: &str, seekable: bool) -> Scm<'g, Port> {
+        let mode = CString::new(mode).unwrap();
+        // ports are shared by threads, their stream is locked for each operation
+        let stream = Box::into_raw(Box::new(Mutex::new(stream))) as scm_t_bits;
+        unsafe {
+            let bits = scm_mode_bits(mode.as_ptr() as *mut libc::c_char) as libc::c_ulong;
+            Scm::_from_raw(scm_c_make_port(port_type(seekable), bits, stream))
+        }
+    }
+
+    /// Change how the port buffers (`setvbuf`)
+    pub fn set_buffering(&self, buffering: Buffering) {
+        let (mode, size) = match buffering {
+            Buffering::None        => ("none", None),
+            Buffering::Line        => ("line", None),
+            Buffering::Block(size) => ("block", Some(size)),
+        };
+        unsafe {
+            let mode = scm_from_utf8_symbol(CString::new(mode).unwrap().as_ptr());
+            let size = size.map(|s| scm_from_uint64(s as u64)).unwrap_or_else(|| gu_SCM_UNDEFINED());
+            scm_setvbuf(self.data, mode, size);
+        }
+    }
+
+    /// Close the port, flushing it first
+    ///
+    /// Errors if the flush fails.
+    pub fn close(&self) -> Result<(), GuileError> {
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || unsafe { scm_close_port(self.data); })
+    }
+}
+
+// the rust side of a port, behind its `SCM_STREAM`
+trait Stream: Send {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> { Err(unsupported("reading")) }
+    fn write(&mut self, _buf: &[u8]) -> io::Result<()> { Err(unsupported("writing")) }
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> { Err(unsupported("seeking")) }
+    fn close(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{} is not supported by this port", what))
+}
+
+struct Reader<R>(R);
+struct Writer<W>(W);
+struct ReadWriteSeek<T>(T);
+
+impl<R: Read + Send> Stream for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.0.read(buf) }
+}
+
+impl<W: Write + Send> Stream for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)?;
+        self.0.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> { self.0.flush() }
+}
+
+impl<T: Read + Write + Seek + Send> Stream for ReadWriteSeek<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.0.read(buf) }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)?;
+        self.0.flush()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> { self.0.seek(pos) }
+
+    fn close(&mut self) -> io::Result<()> { self.0.flush() }
+}
+
+// the port types, made on first use and never freed (as `scm_make_port_type` wants)
+static PORT_TYPES: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+
+fn port_type(seekable: bool) -> *mut scm_t_port_type {
+    let mut types = PORT_TYPES.lock().unwrap_or_else(|e| e.into_inner());
+    let (plain, seekable_) = *types.get_or_insert_with(|| unsafe {
+        let make = |name: &str| {
+            // kept by the port type
+            let name = CString::new(name).unwrap().into_raw();
+            let ptob = scm_make_port_type(name, Some(port_read), Some(port_write));
+            scm_set_port_close(ptob, Some(port_close));
+            scm_set_port_needs_close_on_gc(ptob, 1);
+            ptob
+        };
+
+        let plain = make("rust-port");
+        let seekable = make("rust-seekable-port");
+        scm_set_port_seek(seekable, Some(port_seek));
+        (plain as usize, seekable as usize)
+    });
+
+    (if seekable { seekable_ } else { plain }) as *mut scm_t_port_type
+}
+
+// run `fun` on the stream of `port`, raising its error in scheme
+// the throw jumps over this frame, so nothing may be left owned when it happens
+unsafe fn with_stream<T, F>(subr: &'static str, port: SCM, fun: F) -> T
+    where F: FnOnce(&mut dyn Stream) -> io::Result<T> {
+    let ret = {
+        let stream = &*(gu_SCM_STREAM(port) as *const Mutex<Box<dyn Stream>>);
+        // a panic unwinds through the lock, poisoning it for later operations
+        let run = || match stream.lock() {
+            Ok(mut stream) => fun(&mut **stream).map_err(|e| e.to_string()),
+            Err(_)         => Err("stream left broken by a panic".to_string()),
+        };
+        match panic::catch_unwind(AssertUnwindSafe(run)) {
+            Ok(Ok(r))    => Ok(r),
+            Ok(Err(msg)) => {
+                let mut err = GuileError::new("rust-io-error", &msg);
+                err.subr = Some(subr.to_string());
+                Err(err)
+            },
+            // resumed once the throw reaches rust again
+            Err(p) => unwind::throw_panic("panic in a rust port", p),
+        }
+    };
+
+    match ret {
+        Ok(r)    => r,
+        Err(err) => {
+            let (key, subr, msg, args) = err.into_raw();
+            scm_error_scm(key, subr, msg, args, gu_SCM_BOOL_F());
+            unreachable!()
+        },
+    }
+}
+
+unsafe extern "C" fn port_read(port: SCM, dst: SCM, start: usize, count: usize) -> usize {
+    let buf = slice::from_raw_parts_mut((gu_SCM_BYTEVECTOR_CONTENTS(dst) as *mut u8).offset(start as isize), count);
+    with_stream("port-read", port, |stream| loop {
+        match stream.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            r => return r,
+        }
+    })
+}
+
+unsafe extern "C" fn port_write(port: SCM, src: SCM, start: usize, count: usize) -> usize {
+    let buf = slice::from_raw_parts((gu_SCM_BYTEVECTOR_CONTENTS(src) as *const u8).offset(start as isize), count);
+    with_stream("port-write", port, |stream| stream.write(buf).map(|_| count))
+}
+
+unsafe extern "C" fn port_seek(port: SCM, offset: scm_t_off, whence: libc::c_int) -> scm_t_off {
+    let pos = match whence {
+        libc::SEEK_SET => SeekFrom::Start(offset as u64),
+        libc::SEEK_END => SeekFrom::End(offset),
+        _              => SeekFrom::Current(offset),
+    };
+    with_stream("port-seek", port, |stream| stream.seek(pos).map(|p| p as scm_t_off))
+}
+
+unsafe extern "C" fn port_close(port: SCM) {
+    // called once, on `close-port` or by the GC; an error can't be thrown from the GC,
+    // guile has flushed its own buffer already
+    let stream = Box::from_raw(gu_SCM_STREAM(port) as *mut Mutex<Box<dyn Stream>>);
+    unwind::catch_deferred(move || {
+        let mut stream = stream.into_inner().unwrap_or_else(|e| e.into_inner());
+        let _ = stream.close();
+    });
+}
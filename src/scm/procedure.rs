@@ -0,0 +1,167 @@
+use std::ffi::CString;
+
+use scm::*;
+use scm::String as ScmString;
+use interp::{Guile, GuileCtx};
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+/// A scheme procedure, see `Scm::<Procedure>::from_closure`
+///
+/// Calls run under a catch-all, a throw comes back as a `GuileError` with its backtrace.
+///
+/// # Example
+/// ```rust,ignore
+/// let fmt: Scm<Procedure> = Guile::eval(ctx, "pretty-print-to-string").into_procedure().unwrap();
+///
+/// let s = fmt.call((Scm::from(ctx, "(a b)"),))?;
+/// let s = fmt.builder().arg("(a b)").keyword("indent", 2).call()?;
+/// ```
+#[derive(Debug)]
+pub struct Procedure;
+impl TypeSpec for Procedure {}
+
+impl<'g> Scm<'g, Procedure> {
+    #[inline]
+    fn catch<F: FnOnce() -> SCM>(fun: F) -> Result<Scm<'g, Untyped>, GuileError> {
+        Guile::catch_traced(unsafe { gu_SCM_BOOL_T() }, || Scm::_from_raw(fun()))
+    }
+
+    pub fn call0(&self) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_call_0(self.data) })
+    }
+
+    pub fn call1<A: TypeSpec>(&self, a: &Scm<A>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_call_1(self.data, a.data) })
+    }
+
+    pub fn call2<A: TypeSpec, B: TypeSpec>(&self, a: &Scm<A>, b: &Scm<B>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_call_2(self.data, a.data, b.data) })
+    }
+
+    pub fn call3<A: TypeSpec, B: TypeSpec, C: TypeSpec>(&self, a: &Scm<A>, b: &Scm<B>, c: &Scm<C>)
+        -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_call_3(self.data, a.data, b.data, c.data) })
+    }
+
+    pub fn call4<A: TypeSpec, B: TypeSpec, C: TypeSpec, D: TypeSpec>(&self, a: &Scm<A>, b: &Scm<B>, c: &Scm<C>, d: &Scm<D>)
+        -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_call_4(self.data, a.data, b.data, c.data, d.data) })
+    }
+
+    /// Call with the elements of `args` as arguments
+    pub fn apply(&self, args: &Scm<List>) -> Result<Scm<'g, Untyped>, GuileError> {
+        Self::catch(|| unsafe { scm_apply_0(self.data, args.data) })
+    }
+
+    /// Call with a tuple of rust or scheme values (see [IntoArgs](trait.IntoArgs.html))
+    pub fn call<A: IntoArgs<'g>>(&self, args: A) -> Result<Scm<'g, Untyped>, GuileError> {
+        let args = args.into_args(self.ctx());
+        self.apply(&args)
+    }
+
+    /// Build a call argument by argument, with keyword arguments
+    #[inline]
+    pub fn builder(&self) -> CallBuilder<'g> {
+        CallBuilder { proc_: self.data, args: unsafe { gu_SCM_EOL() }, ctx: self.ctx() }
+    }
+}
+
+/// Arguments of a procedure call, see `Scm::<Procedure>::builder`
+pub struct CallBuilder<'g> {
+    proc_: SCM,
+    // reversed, kept in a scheme list as a `Vec` would hide its values from the GC
+    args:  SCM,
+    ctx:   GuileCtx<'g>,
+}
+
+impl<'g> CallBuilder<'g> {
+    /// Add a positional argument
+    pub fn arg<A: IntoArg<'g>>(mut self, arg: A) -> CallBuilder<'g> {
+        self.args = unsafe { gu_scm_cons(arg.into_arg(self.ctx).data, self.args) };
+        self
+    }
+
+    /// Add a keyword argument, `keyword("indent", 2)` for `#:indent 2`
+    pub fn keyword<A: IntoArg<'g>>(self, name: &str, arg: A) -> CallBuilder<'g> {
+        let kw = unsafe { scm_from_utf8_keyword(CString::new(name).unwrap().as_ptr()) };
+        let kw: Scm<'g, Untyped> = Scm::_from_raw(kw);
+        self.arg(kw).arg(arg)
+    }
+
+    pub fn call(self) -> Result<Scm<'g, Untyped>, GuileError> {
+        let proc_: Scm<'g, Procedure> = Scm::_from_raw(self.proc_);
+        proc_.apply(&Scm::_from_raw(unsafe { scm_reverse(self.args) }))
+    }
+}
+
+/// A rust or scheme value passed as a procedure argument
+pub trait IntoArg<'g> {
+    fn into_arg(self, ctx: GuileCtx<'g>) -> Scm<'g, Untyped>;
+}
+
+impl<'g, TS: TypeSpec> IntoArg<'g> for Scm<'g, TS> {
+    #[inline]
+    fn into_arg(self, _ctx: GuileCtx<'g>) -> Scm<'g, Untyped> { self.into_unspecified() }
+}
+
+impl<'a, 'g, TS: TypeSpec> IntoArg<'g> for &'a Scm<'g, TS> {
+    #[inline]
+    fn into_arg(self, _ctx: GuileCtx<'g>) -> Scm<'g, Untyped> { Scm::_from_raw(self.data) }
+}
+
+impl<'g> IntoArg<'g> for bool {
+    #[inline]
+    fn into_arg(self, ctx: GuileCtx<'g>) -> Scm<'g, Untyped> {
+        if self { Scm::true_c(ctx).into_unspecified() } else { Scm::false_c(ctx).into_unspecified() }
+    }
+}
+
+macro_rules! simple_arg {
+    ($ty:ty, $spec:ty) => {
+        impl<'g> IntoArg<'g> for $ty {
+            #[inline]
+            fn into_arg(self, ctx: GuileCtx<'g>) -> Scm<'g, Untyped> {
+                Scm::<$spec>::from(ctx, self).into_unspecified()
+            }
+        }
+    }
+}
+
+simple_to_scm_types!(simple_arg);
+
+/// A tuple of procedure arguments, implemented up to 8 elements
+pub trait IntoArgs<'g> {
+    fn into_args(self, ctx: GuileCtx<'g>) -> Scm<'g, List>;
+}
+
+macro_rules! into_args_impl {
+    ($($a:ident),*) => {
+        impl<'g, $($a: IntoArg<'g>),*> IntoArgs<'g> for ($($a,)*) {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn into_args(self, ctx: GuileCtx<'g>) -> Scm<'g, List> {
+                let ($($a,)*) = self;
+                // converted values stay on the stack until consed
+                let items = [$($a.into_arg(ctx).data),*];
+
+                let mut list = unsafe { gu_SCM_EOL() };
+                for item in items.iter().rev() {
+                    list = unsafe { gu_scm_cons(*item, list) };
+                }
+                Scm::_from_raw(list)
+            }
+        }
+    }
+}
+
+into_args_impl!();
+into_args_impl!(A1);
+into_args_impl!(A1, A2);
+into_args_impl!(A1, A2, A3);
+into_args_impl!(A1, A2, A3, A4);
+into_args_impl!(A1, A2, A3, A4, A5);
+into_args_impl!(A1, A2, A3, A4, A5, A6);
+into_args_impl!(A1, A2, A3, A4, A5, A6, A7);
+into_args_impl!(A1, A2, A3, A4, A5, A6, A7, A8);
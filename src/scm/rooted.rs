@@ -0,0 +1,227 @@
+use libc;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::ptr;
+
+use scm::Scm;
+use scm::TypeSpec;
+use interp::GuileCtx;
+
+use guile_rs_sys::*;
+
+
+/// A `Scm` value registered as a GC root
+///
+/// Guile only sees values on the C stack and inside its own heap, so anything kept in a
+/// `Vec`, a `Box`, a struct field or a static can be collected while still in use.
+/// A `Rooted` handle protects its value with `scm_gc_protect_object` and releases it on
+/// `Drop`, so it can be stored anywhere in rust memory.
+///
+/// It carries no context lifetime, so it can outlive the `Guile::call_with_guile` it was
+/// made in, the value is taken out again with `get`. Unlike `SendScm` it stays on the
+/// thread that made it, but it can be shared from a static.
+#[derive(Debug)]
+pub struct Rooted<TS: TypeSpec> {
+    data: SCM,
+    spec: PhantomData<TS>,
+    // !Send, the handle stays on its thread
+    thread: PhantomData<*mut ()>,
+}
+
+// shared references only give the value back through a `GuileCtx` of the using thread
+unsafe impl<TS: TypeSpec> Sync for Rooted<TS> {}
+
+impl<TS: TypeSpec> Rooted<TS> {
+    pub fn new<'g>(scm: Scm<'g, TS>) -> Rooted<TS> {
+        unsafe { scm_gc_protect_object(scm.data); }
+        Rooted { data: scm.data, spec: PhantomData, thread: PhantomData }
+    }
+
+    /// Get the value, valid for as long as `ctx`
+    #[inline]
+    pub fn get<'g>(&self, _ctx: GuileCtx<'g>) -> Scm<'g, TS> {
+        Scm::_from_raw(self.data)
+    }
+
+    /// Release the root and get back the plain value
+    ///
+    /// The returned value is only safe as long as it stays on the stack
+    #[inline]
+    pub fn into_inner<'g>(self, ctx: GuileCtx<'g>) -> Scm<'g, TS> {
+        self.get(ctx)
+        // `self` dropped here, unprotecting the object
+    }
+}
+
+impl<TS: TypeSpec> Drop for Rooted<TS> {
+    fn drop(&mut self) {
+        // protection is counted, so other roots to the same object stay valid. The handle
+        // may outlive guile mode, `scm_with_guile` enters it if needed
+        unsafe { scm_with_guile(Some(unprotect), self.data as *mut libc::c_void); }
+    }
+}
+
+impl<TS: TypeSpec> Clone for Rooted<TS> {
+    fn clone(&self) -> Rooted<TS> {
+        unsafe { scm_with_guile(Some(protect), self.data as *mut libc::c_void); }
+        Rooted { data: self.data, spec: PhantomData, thread: PhantomData }
+    }
+}
+
+impl<'g, TS: TypeSpec> From<Scm<'g, TS>> for Rooted<TS> {
+    #[inline]
+    fn from(scm: Scm<'g, TS>) -> Rooted<TS> { Rooted::new(scm) }
+}
+
+unsafe extern "C" fn protect(data: *mut libc::c_void) -> *mut libc::c_void {
+    scm_gc_protect_object(data as SCM);
+    ptr::null_mut()
+}
+
+unsafe extern "C" fn unprotect(data: *mut libc::c_void) -> *mut libc::c_void {
+    scm_gc_unprotect_object(data as SCM);
+    ptr::null_mut()
+}
+
+impl<'g, TS: TypeSpec> Scm<'g, TS> {
+    /// Protect this value from the GC (see [Rooted](struct.Rooted.html))
+    #[inline]
+    pub fn into_rooted(self) -> Rooted<TS> { Rooted::new(self) }
+
+    /// Protect this value and make it sendable (see [SendScm](struct.SendScm.html))
+    #[inline]
+    pub fn into_send(self) -> SendScm<TS> { SendScm::new(self) }
+}
+
+
+/// An arena keeping every value rooted through it alive until it is dropped
+///
+/// Where `Rooted` pays one `scm_gc_protect_object` per value, a `RootScope` stores its
+/// values in a single protected scheme vector (grown by doubling), which makes it cheap to
+/// hold thousands of temporaries in rust collections.
+///
+/// Values rooted through a scope borrow it, so they cannot outlive it.
+///
+/// # Example
+/// ```rust,ignore
+/// RootScope::scope(ctx, |scope| {
+///     let elems: Vec<Scm<Int>> = (0..10000).map(|i| scope.root(Scm::from(ctx, i))).collect();
+///     let list: Scm<List> = Scm::from(ctx, elems);
+/// });
+/// ```
+#[derive(Debug)]
+pub struct RootScope<'g> {
+    store: Cell<SCM>,
+    len:   Cell<usize>,
+    cap:   Cell<usize>,
+    ctx:   PhantomData<GuileCtx<'g>>,
+}
+
+impl<'g> RootScope<'g> {
+    pub fn new(ctx: GuileCtx<'g>) -> RootScope<'g> {
+        RootScope::with_capacity(ctx, 32)
+    }
+
+    pub fn with_capacity(_ctx: GuileCtx<'g>, cap: usize) -> RootScope<'g> {
+        let cap = cap.max(1);
+        let store = unsafe {
+            let v = scm_c_make_vector(cap, gu_SCM_BOOL_F());
+            scm_gc_protect_object(v)
+        };
+
+        RootScope {
+            store: Cell::new(store),
+            len:   Cell::new(0),
+            cap:   Cell::new(cap),
+            ctx:   PhantomData,
+        }
+    }
+
+    /// Run `fun` with a new scope, releasing all its values when it returns
+    pub fn scope<F: FnOnce(&RootScope<'g>) -> R, R>(ctx: GuileCtx<'g>, fun: F) -> R {
+        let scope = RootScope::new(ctx);
+        fun(&scope)
+    }
+
+    /// Keep `scm` alive for as long as the scope lives
+    pub fn root<'s, TS: TypeSpec>(&'s self, scm: Scm<'g, TS>) -> Scm<'s, TS> {
+        let len = self.len.get();
+        if len == self.cap.get() {
+            self.grow();
+        }
+
+        unsafe { scm_c_vector_set_x(self.store.get(), len, scm.data); }
+        self.len.set(len + 1);
+
+        Scm::_from_raw(scm.data)
+    }
+
+    /// Number of values rooted in this scope
+    #[inline]
+    pub fn len(&self) -> usize { self.len.get() }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len.get() == 0 }
+
+    fn grow(&self) {
+        let (old, len) = (self.store.get(), self.len.get());
+        let cap = self.cap.get() * 2;
+
+        unsafe {
+            let new = scm_gc_protect_object(scm_c_make_vector(cap, gu_SCM_BOOL_F()));
+            for i in 0..len {
+                scm_c_vector_set_x(new, i, scm_c_vector_ref(old, i));
+            }
+            scm_gc_unprotect_object(old);
+            self.store.set(new);
+        }
+
+        self.cap.set(cap);
+    }
+}
+
+impl<'g> Drop for RootScope<'g> {
+    fn drop(&mut self) {
+        unsafe { scm_gc_unprotect_object(self.store.get()); }
+    }
+}
+
+
+/// A GC-protected handle that can cross threads
+///
+/// Unlike `Scm` it carries no context lifetime: it can be stored in statics, sent to
+/// worker threads or kept across `Guile::call_with_guile` calls. The value can only be
+/// taken out again with a `GuileCtx`, which proves the current thread is in guile mode.
+#[derive(Debug)]
+pub struct SendScm<TS: TypeSpec> {
+    root: Rooted<TS>,
+}
+
+// the raw value is only reachable through a `GuileCtx` of the using thread, and the root
+// is released with `scm_with_guile` whatever the thread dropping it
+unsafe impl<TS: TypeSpec> Send for SendScm<TS> {}
+
+impl<TS: TypeSpec> SendScm<TS> {
+    #[inline]
+    pub fn new<'g>(scm: Scm<'g, TS>) -> SendScm<TS> {
+        SendScm { root: Rooted::new(scm) }
+    }
+
+    /// Get the value back on a thread in guile mode
+    #[inline]
+    pub fn get<'g>(&self, ctx: GuileCtx<'g>) -> Scm<'g, TS> {
+        self.root.get(ctx)
+    }
+}
+
+impl<TS: TypeSpec> Clone for SendScm<TS> {
+    #[inline]
+    fn clone(&self) -> SendScm<TS> {
+        SendScm { root: self.root.clone() }
+    }
+}
+
+impl<'g, TS: TypeSpec> From<Scm<'g, TS>> for SendScm<TS> {
+    #[inline]
+    fn from(scm: Scm<'g, TS>) -> SendScm<TS> { SendScm::new(scm) }
+}
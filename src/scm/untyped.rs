@@ -1,3 +1,5 @@
+use std;
+
 use scm::*;
 use scm::String as ScmString;
 
@@ -6,7 +8,7 @@ use scm::String as ScmString;
 pub struct Untyped;
 impl TypeSpec for Untyped {}
 
-impl Scm<Untyped> {
+impl<'g> Scm<'g, Untyped> {
     into_type!(into_bool,        is_bool,       Bool);
     into_type!(into_string,      is_string,     ScmString);
     into_type!(into_integer,     is_integer,    Int);
@@ -17,4 +19,56 @@ impl Scm<Untyped> {
     into_type!(into_hashq_table, is_hash_table, HashQTable);
     into_type!(into_hashv_table, is_hash_table, HashVTable);
     into_type!(into_hashx_table, is_hash_table, HashXTable);
+    into_type!(into_procedure,   is_procedure,  Procedure);
+    into_type!(into_variable,    is_variable,   Variable);
+    into_type!(into_port,        is_port,       Port);
+}
+
+macro_rules! typed_from_scm {
+    ($into:ident, $spec:ident, $name:expr) => {
+        impl<'g> FromScm<'g> for Scm<'g, $spec> {
+            #[inline]
+            fn from_scm(scm: Scm<'g, Untyped>) -> Option<Scm<'g, $spec>> { scm.$into().ok() }
+
+            #[inline]
+            fn type_name() -> &'static str { $name }
+        }
+    }
+}
+
+impl<'g> FromScm<'g> for Scm<'g, Untyped> {
+    #[inline]
+    fn from_scm(scm: Scm<'g, Untyped>) -> Option<Scm<'g, Untyped>> { Some(scm) }
+
+    #[inline]
+    fn type_name() -> &'static str { "any value" }
+}
+
+typed_from_scm!(into_bool,       Bool,      "boolean");
+typed_from_scm!(into_string,     ScmString, "string");
+typed_from_scm!(into_integer,    Int,       "exact integer");
+typed_from_scm!(into_symbol,     Symbol,    "symbol");
+typed_from_scm!(into_pair,       Pair,      "pair");
+typed_from_scm!(into_list,       List,      "list");
+typed_from_scm!(into_hash_table, HashTable, "hash table");
+typed_from_scm!(into_procedure,  Procedure, "procedure");
+typed_from_scm!(into_variable,   Variable,  "variable");
+typed_from_scm!(into_port,       Port,      "port");
+
+impl<'g> FromScm<'g> for bool {
+    fn from_scm(scm: Scm<'g, Untyped>) -> Option<bool> {
+        scm.into_bool().ok().map(|b| b.to_bool())
+    }
+
+    #[inline]
+    fn type_name() -> &'static str { "boolean" }
+}
+
+impl<'g> FromScm<'g> for std::string::String {
+    fn from_scm(scm: Scm<'g, Untyped>) -> Option<std::string::String> {
+        scm.into_string().ok().map(|s| s.to_string())
+    }
+
+    #[inline]
+    fn type_name() -> &'static str { "string" }
 }
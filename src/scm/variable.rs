@@ -0,0 +1,80 @@
+use std::ffi::CString;
+
+use scm::*;
+use interp::{Guile, GuileCtx};
+
+use guile_rs_sys::*;
+
+
+/// A scheme variable, the box a top level binding is stored in
+///
+/// Looking a variable up once and keeping the handle avoids parsing and looking up the name
+/// on every access. It follows later `set!`s and re-definitions of the binding.
+///
+/// # Example
+/// ```rust,ignore
+/// let tab_width = Scm::<Variable>::lookup(ctx, "tab-width").unwrap();
+/// for line in lines {
+///     let width = tab_width.get().and_then(|w| w.into_integer().ok());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Variable;
+impl TypeSpec for Variable {}
+
+impl<'g> Scm<'g, Variable> {
+    /// A new variable, not bound to any name
+    #[inline]
+    pub fn new<TS: TypeSpec>(_ctx: GuileCtx<'g>, value: &Scm<TS>) -> Scm<'g, Variable> {
+        Scm::_from_raw(unsafe { scm_make_variable(value.data) })
+    }
+
+    /// The variable of `name` in the current module, if defined
+    ///
+    /// Unlike `scm_c_lookup`, an unknown name is not an error.
+    pub fn lookup(ctx: GuileCtx<'g>, name: &str) -> Option<Scm<'g, Variable>> {
+        Scm::<Module>::current(ctx).variable(name)
+    }
+
+    /// The value, `None` if the variable is unbound
+    #[inline]
+    pub fn get(&self) -> Option<Scm<'g, Untyped>> {
+        if self.is_bound() {
+            Some(Scm::_from_raw(unsafe { scm_variable_ref(self.data) }))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn set<TS: TypeSpec>(&self, value: &Scm<TS>) {
+        unsafe { scm_variable_set_x(self.data, value.data); }
+    }
+
+    #[inline]
+    pub fn is_bound(&self) -> bool {
+        unsafe { gu_scm_is_true(scm_variable_bound_p(self.data)) }
+    }
+}
+
+impl<'g> Scm<'g, Module> {
+    /// The variable of `name` in this module or the ones it uses, if defined
+    pub fn variable(&self, name: &str) -> Option<Scm<'g, Variable>> {
+        let sym = unsafe { scm_from_utf8_symbol(CString::new(name).unwrap().as_ptr()) };
+        let var = unsafe { scm_module_variable(self.data, sym) };
+        if unsafe { gu_scm_is_false(var) } { None } else { Some(Scm::_from_raw(var)) }
+    }
+}
+
+impl Guile {
+    /// The value of `name` in the current module, `None` if it is not defined
+    pub fn lookup<'g>(ctx: GuileCtx<'g>, name: &str) -> Option<Scm<'g, Untyped>> {
+        Scm::<Variable>::lookup(ctx, name).and_then(|var| var.get())
+    }
+
+    /// Define `name` as `value` in the current module, returning its variable
+    pub fn define<'g, TS: TypeSpec>(_ctx: GuileCtx<'g>, name: &str, value: &Scm<TS>) -> Scm<'g, Variable> {
+        let name = CString::new(name).unwrap();
+        Scm::_from_raw(unsafe { scm_c_define(name.as_ptr(), value.data) })
+    }
+}
@@ -0,0 +1,187 @@
+//! Rust panics at the boundaries with libguile
+//!
+//! Unwinding through libguile's C frames is undefined behaviour, so every rust function
+//! called back from C catches panics and hands them back to rust once control returns.
+//!
+//! A panic that can be thrown (in a rust procedure or port) travels in the scheme throw
+//! and is resumed by the rust catch it reaches. If scheme handles the throw, it is dropped
+//! with it. Other panics (in finalizers and dynwind handlers) are kept in the innermost
+//! boundary open on the thread, and resumed when that boundary returns to rust.
+
+use libc;
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+
+use error::GuileError;
+
+use guile_rs_sys::*;
+
+
+/// A rust closure called back from C, with caller-owned storage for its result
+pub(crate) struct Callback<F: FnOnce() -> R, R> {
+    fun:      Option<F>,
+    ret:      Option<thread::Result<R>>,
+    boundary: Option<Boundary>,
+}
+
+impl<F: FnOnce() -> R, R> Callback<F, R> {
+    pub(crate) fn new(fun: F) -> Callback<F, R> {
+        Callback { fun: Some(fun), ret: None, boundary: None }
+    }
+
+    /// Pointer to pass as the `data` of a C callback
+    ///
+    /// Opens the boundary panics deferred until `into_result` are kept in.
+    #[inline]
+    pub(crate) fn as_ptr(&mut self) -> *mut libc::c_void {
+        if self.boundary.is_none() {
+            self.boundary = Some(Boundary::open());
+        }
+        self as *mut Callback<F, R> as *mut libc::c_void
+    }
+
+    /// Run the closure behind `data`, storing its result or panic
+    ///
+    /// IMPORTANT: `data` has to come from `as_ptr` on a `Callback<F, R>`
+    pub(crate) unsafe fn run(data: *mut libc::c_void) {
+        let callback = &mut *(data as *mut Callback<F, R>);
+        if let Some(fun) = callback.fun.take() {
+            callback.ret = Some(panic::catch_unwind(AssertUnwindSafe(fun)));
+        }
+    }
+
+    /// Get the result, resuming the panic if the closure panicked
+    ///
+    /// `None` if scheme left the closure with a non-local exit before it returned
+    pub(crate) fn into_result(self) -> Option<R> {
+        if let Some(boundary) = self.boundary {
+            boundary.close();
+        }
+        match self.ret {
+            Some(Ok(r))  => Some(r),
+            Some(Err(p)) => panic::resume_unwind(p),
+            None         => None,
+        }
+    }
+}
+
+thread_local! {
+    // a slot for each boundary open on this thread, the innermost last
+    static DEFERRED: RefCell<Vec<Option<Box<dyn Any + Send>>>> = RefCell::new(Vec::new());
+}
+
+/// A return to rust that panics deferred while it is open are resumed at
+///
+/// Boundaries nest, a deferred panic goes to the innermost one. Dropping one without
+/// `close`, when rust unwinds over it, discards its panic.
+pub(crate) struct Boundary {
+    depth:  usize,
+    closed: bool,
+}
+
+impl Boundary {
+    pub(crate) fn open() -> Boundary {
+        let depth = DEFERRED.with(|d| {
+            let mut d = d.borrow_mut();
+            d.push(None);
+            d.len()
+        });
+        Boundary { depth, closed: false }
+    }
+
+    /// Close the boundary, resuming a panic deferred while it was open
+    pub(crate) fn close(mut self) {
+        self.closed = true;
+        if let Some(p) = self.take() {
+            panic::resume_unwind(p);
+        }
+    }
+
+    fn take(&self) -> Option<Box<dyn Any + Send>> {
+        DEFERRED.try_with(|d| {
+            let mut d = d.borrow_mut();
+            // inner boundaries a throw jumped over never closed, their panics come here
+            let first = d.drain(self.depth - 1..).filter_map(|p| p).next();
+            first
+        }).unwrap_or(None)
+    }
+}
+
+impl Drop for Boundary {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.take();
+        }
+    }
+}
+
+/// Run `fun`, keeping a panic for the innermost boundary instead of unwinding
+pub(crate) fn catch_deferred<F: FnOnce()>(fun: F) {
+    if let Err(p) = panic::catch_unwind(AssertUnwindSafe(fun)) {
+        defer(p);
+    }
+}
+
+/// Keep a caught panic for the innermost boundary of this thread
+///
+/// With no boundary open (a finalizer run outside of any call into guile) the panic is
+/// dropped, its message has been printed already.
+pub(crate) fn defer(p: Box<dyn Any + Send>) {
+    let _ = DEFERRED.try_with(|d| {
+        if let Some(slot) = d.borrow_mut().last_mut() {
+            // keep the first one, later panics are most likely caused by it
+            if slot.is_none() {
+                *slot = Some(p);
+            }
+        }
+    });
+}
+
+// addresses of the panics carried by throws still in flight, so scheme can't forge one
+static CARRIED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Throw `p` as a scheme `rust-panic` error, the rust catch it reaches resumes it
+///
+/// IMPORTANT: the throw jumps over the calling frame, nothing owned by rust may be left in it
+pub(crate) unsafe fn throw_panic(message: &str, p: Box<dyn Any + Send>) -> ! {
+    let (key, subr, msg, args) = GuileError::new("rust-panic", message).into_raw();
+
+    let carried = Box::into_raw(Box::new(Some(p)));
+    CARRIED.lock().unwrap_or_else(|e| e.into_inner()).push(carried as usize);
+    let carrier = scm_from_pointer(carried as *mut libc::c_void, Some(drop_carried));
+
+    scm_error_scm(key, subr, msg, args, carrier);
+    unreachable!()
+}
+
+// finalizer of the carrier, once the throw was handled in scheme
+unsafe extern "C" fn drop_carried(data: *mut libc::c_void) {
+    CARRIED.lock().unwrap_or_else(|e| e.into_inner()).retain(|&c| c != data as usize);
+    let carried = Box::from_raw(data as *mut Option<Box<dyn Any + Send>>);
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(carried)));
+}
+
+/// The panic carried by a caught throw, if it is a `throw_panic` one
+///
+/// Only call in guile mode
+pub(crate) unsafe fn take_thrown(key: SCM, args: SCM) -> Option<Box<dyn Any + Send>> {
+    let rust_panic = scm_from_utf8_symbol(CString::new("rust-panic").unwrap().as_ptr());
+    if !gu_scm_is_eq(key, rust_panic) || scm_ilength(args) != 4 {
+        return None;
+    }
+
+    let carrier = scm_list_ref(args, scm_from_int32(3));
+    if gu_scm_is_false(scm_pointer_p(carrier)) {
+        return None;
+    }
+
+    let carried = scm_to_pointer(carrier) as *mut Option<Box<dyn Any + Send>>;
+    if !CARRIED.lock().unwrap_or_else(|e| e.into_inner()).contains(&(carried as usize)) {
+        return None;
+    }
+    (*carried).take()
+}
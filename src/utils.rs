@@ -2,7 +2,7 @@ macro_rules! into_type {
     ($tn:ident, $spec:ident) => (into_type!(into_$tn, is_$tn, $spec););
 
     ($inn:ident, $isn:ident, $spec:ident) => {
-        pub fn $inn(self) -> Result<Scm<$spec>, ()> {
+        pub fn $inn(self) -> Result<Scm<'g, $spec>, ()> {
             if self.$isn() {
                 Ok(self.into_type())
             } else {
@@ -19,7 +19,7 @@ macro_rules! is_thing_p {
     ($fname:ident ($($an:ident: $tn:ident <$at:path>),*) => $cfunc:ident) => {
         // /// Retrun guile true value when is condition
         #[inline]
-        pub fn $fname<$($tn: $at),*>(&self, $($an: &Scm<$tn>),*) -> Scm<Bool> {
+        pub fn $fname<$($tn: $at),*>(&self, $($an: &Scm<$tn>),*) -> Scm<'g, Bool> {
             Scm::_from_raw(unsafe { $cfunc(self.data, $($an.data),*) })
         }
     };
@@ -82,12 +82,9 @@ macro_rules! scm_func {
 #[allow(unused_macros)]
 macro_rules! simple_from {
     ($from:ty, $cfunc: ident, $to:ty) => {
-        impl From<$from> for $to {
-            fn from(f: $from) -> $to {
-                Self {
-                    data: unsafe { $cfunc(f) },
-                    spec: PhantomData,
-                }
+        impl<'g> IntoScm<'g, $to> for $from {
+            fn into_scm(self, _ctx: GuileCtx<'g>) -> Scm<'g, $to> {
+                Scm::_from_raw(unsafe { $cfunc(self) })
             }
         }
     };
@@ -96,12 +93,11 @@ macro_rules! simple_from {
 #[allow(unused_macros)]
 macro_rules! simple_try_as {
     ($from:ty, $cfunc:ident, $to:ty) => {
-        impl TryAs<$to, ()> for $from {
+        impl<'g> TryAs<$to, ()> for Scm<'g, $from> {
             fn try_as(&self) -> Result<$to, ()> {
                 if self.is_exact_integer() {
-                    // TODO: handle runtime guile errors
-                    // TODO: handle guile int not fitting target type
-                    Ok(unsafe { $cfunc(self.data) })
+                    // out of range values throw
+                    ::interp::Guile::try_call(|| unsafe { $cfunc(self.data) }).map_err(|_| ())
                 } else {
                     Err(())
                 }
@@ -109,3 +105,33 @@ macro_rules! simple_try_as {
         }
     }
 }
+
+macro_rules! simple_from_scm {
+    ($into:ident, $to:ty) => {
+        impl<'g> FromScm<'g> for $to {
+            fn from_scm(scm: Scm<'g, Untyped>) -> Option<$to> {
+                scm.$into().ok().and_then(|s| s.try_as().ok())
+            }
+
+            #[inline]
+            fn type_name() -> &'static str { concat!(stringify!($to), " integer") }
+        }
+    }
+}
+
+// invoke `$m!(type, spec)` for each rust type converting to a scheme value of `spec`,
+// shared by procedure arguments and return values
+macro_rules! simple_to_scm_types {
+    ($m:ident) => {
+        $m!(i8,  Int);
+        $m!(u8,  Int);
+        $m!(i16, Int);
+        $m!(u16, Int);
+        $m!(i32, Int);
+        $m!(u32, Int);
+        $m!(i64, Int);
+        $m!(u64, Int);
+        $m!(::std::string::String, ScmString);
+        $m!(&'static str, ScmString);
+    }
+}
@@ -0,0 +1,58 @@
+// Starting guile with a configured runtime, which needs a process of its own: in the unit
+// tests guile is already started by whichever test runs first.
+
+extern crate guile_rs;
+extern crate libc;
+
+use std::env;
+use std::thread;
+
+use guile_rs::{Guile, GuileRuntime, WarningOutput};
+
+#[test]
+fn runtime_builder_test() {
+    // the only test of this binary, nothing else reads the environment yet
+    env::set_var("GUILE_LOAD_PATH", "/guile-rs/from-env");
+    env::set_var("GUILE_AUTO_COMPILE", "fresh");
+    unsafe { libc::signal(libc::SIGWINCH, libc::SIG_IGN); }
+
+    let runtime = GuileRuntime::builder()
+        .auto_compile(false)
+        .load_path("/guile-rs/scheme")
+        .compiled_load_path("/guile-rs/ccache")
+        .honour_environment(false)
+        .port_encoding("ISO-8859-1")
+        .signal_handlers(false)
+        .warnings(WarningOutput::Discard)
+        .init();
+    assert!(runtime.is_ok());
+    assert!(GuileRuntime::builder().init().is_err());
+
+    // the environment and the signal actions of the process are left alone
+    assert_eq!(env::var("GUILE_LOAD_PATH").unwrap(), "/guile-rs/from-env");
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        assert_eq!(libc::sigaction(libc::SIGWINCH, std::ptr::null(), &mut action), 0);
+        assert_eq!(action.sa_sigaction, libc::SIG_IGN);
+    }
+
+    Guile::call_with_guile(|ctx, _| {
+        let path = Guile::load_path(ctx);
+        assert_eq!(path[0], "/guile-rs/scheme");
+        assert!(path.iter().all(|d| d != "/guile-rs/from-env"));
+        assert_eq!(Guile::load_compiled_path(ctx)[0], "/guile-rs/ccache");
+
+        assert!(!Guile::eval_as::<bool>(ctx, "%load-should-auto-compile").unwrap());
+        assert!(!Guile::eval_as::<bool>(ctx, "%fresh-auto-compile").unwrap());
+        assert!(!Guile::eval_as::<bool>(ctx, "(eq? (current-warning-port) (current-error-port))").unwrap());
+        assert_eq!(Guile::eval_as::<String>(ctx, "(fluid-ref %default-port-encoding)").unwrap(), "ISO-8859-1");
+    }, ());
+
+    // per thread settings apply to threads entering guile later
+    thread::spawn(|| {
+        Guile::call_with_guile(|ctx, _| {
+            assert_eq!(Guile::eval_as::<String>(ctx, "(fluid-ref %default-port-encoding)").unwrap(), "ISO-8859-1");
+            assert!(!Guile::eval_as::<bool>(ctx, "(eq? (current-warning-port) (current-error-port))").unwrap());
+        }, ());
+    }).join().unwrap();
+}